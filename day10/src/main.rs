@@ -1,22 +1,28 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+#[cfg(test)]
+const TESTPROG: &str = include_str!("test.txt");
 
 const TARGET_CYCLES: &[u32] = &[20, 60, 100, 140, 180, 220];
 
 // Part 1
-fn check_cycle(ss: &mut Vec<u32>, cycle: u32, reg: i32) {
+fn check_cycle(ss: &mut Vec<i64>, cycle: u32, reg: i32) {
     if TARGET_CYCLES.contains(&cycle) {
-        ss.push(cycle * reg as u32);
+        ss.push(cycle as i64 * reg as i64);
     }
 }
 
 // Part 2
-fn check_cycle2(crt: &mut Vec<String>, cycle: u32, reg: i32) {
+fn check_cycle2(crt: &mut [String], cycle: u32, reg: i32) {
     let row: usize = (cycle / 40) as usize;
     let col = (cycle % 40) as usize;
 
-    let sprite: Vec<i32> = (reg - 1..reg + 2)
-        .filter(|p| p >= &0 && p < &40)
+    let sprite: Vec<i32> = (reg.saturating_sub(1)..reg.saturating_add(2))
+        .filter(|p| (0..40).contains(p))
         .collect::<Vec<i32>>();
 
     if sprite.contains(&(col as i32)) {
@@ -24,41 +30,1163 @@ fn check_cycle2(crt: &mut Vec<String>, cycle: u32, reg: i32) {
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string("src/puzzle.txt")?;
-
-    let mut reg: i32 = 1;
-    let mut cycle: u32 = 0;
-    let mut ss: Vec<u32> = Vec::new();
-    let mut crt: Vec<String> = vec![".".repeat(40); 6];
-    // let mut pixels: String = "".to_string();
-
-    for instruction in contents.split("\n").collect::<Vec<&str>>() {
-        match instruction.split(" ").collect::<Vec<&str>>()[..] {
-            [_, val] => {
-                for i in 0..2 {
-                    check_cycle2(&mut crt, cycle, reg);
-                    cycle += 1;
-                    check_cycle(&mut ss, cycle, reg);
+// The letters AoC draws on the CRT, 4x6 each, rows joined top to bottom
+const GLYPHS: &[(char, &str)] = &[
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+// What the screen says. Unrecognised cells read as '?' and are listed by position.
+#[derive(Debug, Clone, PartialEq)]
+struct Reading {
+    text: String,
+    unknown: Vec<usize>,
+}
+
+// Letters sit in 5 column cells, 4 columns of glyph and a blank one. Empty cells read as
+// spaces.
+fn read_crt(rows: &[String]) -> Reading {
+    let width = rows.iter().map(|r| r.len()).min().unwrap_or(0);
+    let mut text = String::new();
+    let mut unknown = Vec::new();
+
+    for cell in 0..width / 5 {
+        let cols = cell * 5..cell * 5 + 4;
+        let glyph: String = rows.iter().map(|r| &r[cols.clone()]).collect();
+        let spacer_lit = rows.iter().any(|r| r.as_bytes()[cell * 5 + 4] == b'#');
+
+        let c = if spacer_lit {
+            None
+        } else if !glyph.contains('#') {
+            Some(' ')
+        } else {
+            GLYPHS.iter().find(|(_, g)| *g == glyph).map(|(c, _)| *c)
+        };
+
+        text.push(c.unwrap_or('?'));
+        if c.is_none() {
+            unknown.push(cell);
+        }
+    }
+
+    Reading { text, unknown }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Reg {
+    X,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Reg {
+    fn parse(s: &str) -> Option<Reg> {
+        match s {
+            "x" => Some(Reg::X),
+            "a" => Some(Reg::A),
+            "b" => Some(Reg::B),
+            "c" => Some(Reg::C),
+            "d" => Some(Reg::D),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Reg::X => "x",
+            Reg::A => "a",
+            Reg::B => "b",
+            Reg::C => "c",
+            Reg::D => "d",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// An operand, a number or a register
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Val {
+    Imm(i32),
+    Reg(Reg),
+}
+
+impl Val {
+    fn parse(s: &str) -> Result<Val, String> {
+        match Reg::parse(s) {
+            Some(r) => Ok(Val::Reg(r)),
+            None => s
+                .parse()
+                .map(Val::Imm)
+                .map_err(|_| format!("bad operand {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Val::Imm(v) => write!(f, "{}", v),
+            Val::Reg(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+// noop and addx are what the handheld runs, the rest is ours. Jump targets are
+// instruction indexes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Instr {
+    Noop,
+    Addx(Val),
+    Subx(Val),
+    Mulx(Val),
+    Mov(Reg, Val),
+    Add(Reg, Val),
+    Jmp(usize),
+    Jnz(Reg, usize),
+}
+
+impl Instr {
+    // `labels` resolves jump targets
+    fn parse(line: &str, labels: &HashMap<String, usize>) -> Result<Instr, String> {
+        let target = |l: &str| {
+            labels
+                .get(l)
+                .copied()
+                .ok_or(format!("unknown label {:?}", l))
+        };
+        let reg = |r: &str| Reg::parse(r).ok_or(format!("bad register {:?}", r));
+
+        match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["noop"] => Ok(Instr::Noop),
+            ["addx", val] => Ok(Instr::Addx(Val::parse(val)?)),
+            ["subx", val] => Ok(Instr::Subx(Val::parse(val)?)),
+            ["mulx", val] => Ok(Instr::Mulx(Val::parse(val)?)),
+            ["mov", r, val] => Ok(Instr::Mov(reg(r)?, Val::parse(val)?)),
+            ["add", r, val] => Ok(Instr::Add(reg(r)?, Val::parse(val)?)),
+            ["jmp", l] => Ok(Instr::Jmp(target(l)?)),
+            ["jnz", r, l] => Ok(Instr::Jnz(reg(r)?, target(l)?)),
+            _ => Err(format!("unknown instruction {:?}", line)),
+        }
+    }
+
+    // How many cycles the instruction takes, its effect lands at the end of the last one
+    fn cycles(&self) -> u32 {
+        match self {
+            Instr::Noop | Instr::Mov(..) => 1,
+            Instr::Addx(_) | Instr::Subx(_) | Instr::Add(..) => 2,
+            Instr::Jmp(_) | Instr::Jnz(..) => 2,
+            Instr::Mulx(_) => 3,
+        }
+    }
+
+    fn target(&self) -> Option<usize> {
+        match self {
+            Instr::Jmp(t) | Instr::Jnz(_, t) => Some(*t),
+            _ => None,
+        }
+    }
+}
+
+// Jump targets print as the labels disassemble gives them
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Noop => write!(f, "noop"),
+            Instr::Addx(v) => write!(f, "addx {}", v),
+            Instr::Subx(v) => write!(f, "subx {}", v),
+            Instr::Mulx(v) => write!(f, "mulx {}", v),
+            Instr::Mov(r, v) => write!(f, "mov {} {}", r, v),
+            Instr::Add(r, v) => write!(f, "add {} {}", r, v),
+            Instr::Jmp(t) => write!(f, "jmp L{}", t),
+            Instr::Jnz(r, t) => write!(f, "jnz {} L{}", r, t),
+        }
+    }
+}
+
+// Assembler. One instruction or `label:` per line, `;` starts a comment and blank lines
+// are skipped. Errors carry the line number.
+fn parse_program(src: &str) -> Result<Vec<Instr>, String> {
+    let lines: Vec<(usize, &str)> = src
+        .lines()
+        .map(|l| l.split(';').next().unwrap_or("").trim())
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty())
+        .collect();
+
+    let mut labels = HashMap::new();
+    let mut pc = 0;
+    for (n, l) in lines.iter() {
+        match l.strip_suffix(':') {
+            Some(label) => {
+                let bad = label.is_empty()
+                    || Reg::parse(label).is_some()
+                    || !label.chars().all(|c| c.is_alphanumeric() || c == '_');
+                if bad {
+                    return Err(format!("line {}: bad label {:?}", n + 1, label));
+                }
+                if labels.insert(label.to_string(), pc).is_some() {
+                    return Err(format!("line {}: label {:?} defined twice", n + 1, label));
                 }
-                let v: i32 = val.parse().unwrap();
-                reg += v;
             }
+            None => pc += 1,
+        }
+    }
+
+    lines
+        .iter()
+        .filter(|(_, l)| !l.ends_with(':'))
+        .map(|(n, l)| Instr::parse(l, &labels).map_err(|e| format!("line {}: {}", n + 1, e)))
+        .collect()
+}
+
+// Back to source, jump targets get labels and each line notes what it costs. Cycle
+// numbers are given as long as they're known without running, up to the first label.
+fn disassemble(program: &[Instr]) -> String {
+    let targets: HashSet<usize> = program.iter().filter_map(|i| i.target()).collect();
+    let mut s = String::new();
+    let mut cycle = Some(1);
+
+    for (pc, instr) in program.iter().enumerate() {
+        if targets.contains(&pc) {
+            s += &format!("L{}:\n", pc);
+            cycle = None;
+        }
 
+        let line = format!("    {}", instr);
+        let cost = instr.cycles();
+        let note = match cycle {
+            Some(c) if cost == 1 => format!("cycle {}", c),
+            Some(c) => format!("cycles {}-{}", c, c + cost - 1),
+            None => format!("{} cycle{}", cost, if cost == 1 { "" } else { "s" }),
+        };
+        s += &format!("{:<16}; {}\n", line, note);
+
+        cycle = cycle.map(|c| c + cost);
+        if instr.target().is_some() {
+            cycle = None;
+        }
+    }
+
+    if targets.contains(&program.len()) {
+        s += &format!("L{}:\n", program.len());
+    }
+    s
+}
+
+// Told about every cycle while it runs, with X as it is during that cycle
+trait Observer {
+    // cycle counts from 1
+    fn on_cycle(&mut self, cycle: u32, x: i32);
+}
+
+// Part 1 as an observer
+#[derive(Debug, Default)]
+struct SignalStrength {
+    strengths: Vec<i64>,
+}
+
+impl SignalStrength {
+    fn total(&self) -> i64 {
+        self.strengths.iter().sum()
+    }
+}
+
+impl Observer for SignalStrength {
+    fn on_cycle(&mut self, cycle: u32, x: i32) {
+        check_cycle(&mut self.strengths, cycle, x);
+    }
+}
+
+// Part 2 as an observer, cycles past the bottom of the screen are dropped
+#[derive(Debug)]
+struct Crt {
+    rows: Vec<String>,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt {
+            rows: vec![".".repeat(40); 6],
+        }
+    }
+}
+
+impl Observer for Crt {
+    fn on_cycle(&mut self, cycle: u32, x: i32) {
+        if cycle <= 240 {
+            check_cycle2(&mut self.rows, cycle - 1, x);
+        }
+    }
+}
+
+struct Cpu<'a> {
+    program: Vec<Instr>,
+    // X, A, B, C, D
+    regs: [i32; 5],
+    // Cycles completed so far
+    cycle: u32,
+    // Next instruction, and how many of its cycles are already done
+    pc: usize,
+    busy: u32,
+    observers: Vec<&'a mut dyn Observer>,
+}
+
+impl<'a> Cpu<'a> {
+    fn new(program: Vec<Instr>) -> Self {
+        Cpu {
+            program,
+            regs: [1, 0, 0, 0, 0],
+            cycle: 0,
+            pc: 0,
+            busy: 0,
+            observers: Vec::new(),
+        }
+    }
+
+    fn observe(&mut self, observer: &'a mut dyn Observer) {
+        self.observers.push(observer);
+    }
+
+    fn reg(&self, r: Reg) -> i32 {
+        self.regs[r as usize]
+    }
+
+    fn x(&self) -> i32 {
+        self.reg(Reg::X)
+    }
+
+    fn val(&self, v: Val) -> i32 {
+        match v {
+            Val::Imm(i) => i,
+            Val::Reg(r) => self.reg(r),
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    // Run one cycle, false once the program has ended
+    fn tick(&mut self) -> bool {
+        let Some(&instr) = self.program.get(self.pc) else {
+            return false;
+        };
+
+        self.cycle += 1;
+        let x = self.x();
+        for o in self.observers.iter_mut() {
+            o.on_cycle(self.cycle, x);
+        }
+
+        self.busy += 1;
+        if self.busy == instr.cycles() {
+            self.execute(instr);
+            self.busy = 0;
+        }
+
+        true
+    }
+
+    // Registers are 32 bits and wrap around on overflow
+    fn execute(&mut self, instr: Instr) {
+        self.pc += 1;
+        let x = self.x();
+        match instr {
+            Instr::Noop => {}
+            Instr::Addx(v) => self.regs[Reg::X as usize] = x.wrapping_add(self.val(v)),
+            Instr::Subx(v) => self.regs[Reg::X as usize] = x.wrapping_sub(self.val(v)),
+            Instr::Mulx(v) => self.regs[Reg::X as usize] = x.wrapping_mul(self.val(v)),
+            Instr::Mov(r, v) => self.regs[r as usize] = self.val(v),
+            Instr::Add(r, v) => self.regs[r as usize] = self.reg(r).wrapping_add(self.val(v)),
+            Instr::Jmp(t) => self.pc = t,
+            Instr::Jnz(r, t) => {
+                if self.reg(r) != 0 {
+                    self.pc = t
+                }
+            }
+        }
+    }
+
+    // Finish the current instruction
+    fn step(&mut self) -> bool {
+        let mut ran = false;
+        while self.tick() {
+            ran = true;
+            if self.busy == 0 {
+                break;
+            }
+        }
+        ran
+    }
+
+    fn run(&mut self) {
+        while !self.halted() {
+            self.step();
+        }
+    }
+}
+
+// X during each cycle
+#[derive(Debug, Default)]
+struct Trace {
+    xs: Vec<i32>,
+}
+
+impl Observer for Trace {
+    fn on_cycle(&mut self, _cycle: u32, x: i32) {
+        self.xs.push(x);
+    }
+}
+
+// addx/noop program giving the same X on every cycle as `xs`, starting from X=1. Only
+// possible while X holds each new value for at least two cycles, as addx needs them.
+fn program_for_trace(xs: &[i32]) -> Result<Vec<Instr>, String> {
+    let mut program = Vec::new();
+    let mut x = 1;
+    let mut c = 0;
+
+    while c < xs.len() {
+        if xs[c] != x {
+            return Err(format!(
+                "X goes from {} to {} on cycle {}, addx can't change it that soon",
+                x,
+                xs[c],
+                c + 1
+            ));
+        }
+        match (xs.get(c + 1), xs.get(c + 2)) {
+            (Some(&next), after) if next == x && after != Some(&x) => {
+                let to = after.copied().unwrap_or(x);
+                program.push(Instr::Addx(Val::Imm(to.wrapping_sub(x))));
+                x = to;
+                c += 2;
+            }
             _ => {
-                check_cycle2(&mut crt, cycle, reg);
-                cycle += 1;
-                check_cycle(&mut ss, cycle, reg);
+                program.push(Instr::Noop);
+                c += 1;
             }
         }
     }
 
-    // Part 1
-    println!("part 1: {:?}", ss.iter().sum::<u32>());
+    Ok(program)
+}
+
+// Compile down to what the handheld can run by running the program and replaying its X
+// trace with addx/noop. Programs that don't halt within max_cycles can't be lowered.
+fn lower(program: &[Instr], max_cycles: u32) -> Result<Vec<Instr>, String> {
+    let mut trace = Trace::default();
+    let mut cpu = Cpu::new(program.to_vec());
+    cpu.observe(&mut trace);
+    while cpu.cycle < max_cycles && cpu.tick() {}
+    if !cpu.halted() {
+        return Err(format!("still running after {} cycles", max_cycles));
+    }
+    drop(cpu);
+
+    program_for_trace(&trace.xs)
+}
+
+// Text laid out the way the CRT shows letters, up to 8 of them in 5 column cells
+fn render_text(text: &str) -> Result<Vec<String>, String> {
+    if text.chars().count() > 8 {
+        return Err(format!(
+            "{:?} doesn't fit, the screen holds 8 letters",
+            text
+        ));
+    }
+
+    let mut rows = vec![String::new(); 6];
+    for c in text.chars() {
+        let glyph = match c {
+            ' ' => "........................",
+            _ => GLYPHS
+                .iter()
+                .find(|(g, _)| *g == c.to_ascii_uppercase())
+                .map(|(_, g)| *g)
+                .ok_or(format!("no glyph for {:?}", c))?,
+        };
+        for (row, line) in rows.iter_mut().enumerate() {
+            *line += &glyph[row * 4..row * 4 + 4];
+            line.push('.');
+        }
+    }
+    for line in rows.iter_mut() {
+        *line += &".".repeat(40 - line.len());
+    }
+
+    Ok(rows)
+}
+
+// Pick X for each of the 240 cycles so the CRT draws `rows`, changing X as few times as
+// possible. X starts at 1 and has to hold every value but the last for at least two
+// cycles, so the first two pixels are always lit.
+fn trace_for_image(rows: &[String]) -> Result<Vec<i32>, String> {
+    const XS: std::ops::RangeInclusive<i32> = -2..=41;
+    const N: usize = 44;
+
+    let lit: Vec<bool> = rows
+        .iter()
+        .flat_map(|r| r.bytes().map(|b| b == b'#'))
+        .collect();
+    if rows.len() != 6 || rows.iter().any(|r| r.len() != 40) {
+        return Err("image has to be 6 rows of 40".to_string());
+    }
+    let fits = |c: usize, x: i32| ((x - (c % 40) as i32).abs() <= 1) == lit[c];
+    let idx = |x: i32| (x - XS.start()) as usize;
+
+    // best[x][held] is the fewest changes to get here with X=x, held for two cycles or
+    // more if `held`. from[c] remembers where each state came from.
+    let mut best = [[None; 2]; N];
+    let mut from: Vec<[[(i32, usize); 2]; N]> = vec![[[(0, 0); 2]; N]; 240];
+    if !fits(0, 1) {
+        return Err("can't draw row 0 column 0, the first two pixels are always lit".to_string());
+    }
+    best[idx(1)][0] = Some(0);
+
+    for (c, back) in from.iter_mut().enumerate().skip(1) {
+        let mut next: [[Option<u32>; 2]; N] = [[None; 2]; N];
+        for x in XS {
+            for (held, cost) in best[idx(x)].iter().enumerate() {
+                let Some(cost) = *cost else {
+                    continue;
+                };
+                for nx in XS {
+                    let (nheld, ncost) = match (nx == x, held) {
+                        (true, _) => (1, cost),
+                        (false, 1) => (0, cost + 1),
+                        (false, _) => continue,
+                    };
+                    let slot = &mut next[idx(nx)][nheld];
+                    if fits(c, nx) && slot.is_none_or(|s| ncost < s) {
+                        *slot = Some(ncost);
+                        back[idx(nx)][nheld] = (x, held);
+                    }
+                }
+            }
+        }
+        if next.iter().flatten().all(|s| s.is_none()) {
+            return Err(format!(
+                "can't draw row {} column {}{}",
+                c / 40,
+                c % 40,
+                if c < 2 {
+                    ", the first two pixels are always lit"
+                } else {
+                    ""
+                }
+            ));
+        }
+        best = next;
+    }
+
+    let mut state = XS
+        .flat_map(|x| (0..2).map(move |h| (x, h)))
+        .filter(|&(x, h)| best[idx(x)][h].is_some())
+        .min_by_key(|&(x, h)| best[idx(x)][h])
+        .ok_or("nothing draws the image")?;
+
+    let mut xs = vec![0; 240];
+    for c in (0..240).rev() {
+        xs[c] = state.0;
+        if c > 0 {
+            state = from[c][idx(state.0)][state.1];
+        }
+    }
+
+    Ok(xs)
+}
+
+// An addx/noop program drawing `rows`, checked by running it
+fn draw_program(rows: &[String]) -> Result<Vec<Instr>, String> {
+    let program = program_for_trace(&trace_for_image(rows)?)?;
+
+    let mut crt = Crt::default();
+    let mut cpu = Cpu::new(program.clone());
+    cpu.observe(&mut crt);
+    cpu.run();
+    drop(cpu);
+
+    if crt.rows != rows {
+        return Err("generated program draws something else".to_string());
+    }
+    Ok(program)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Breakpoint {
+    // Stop once this cycle has run
+    Cycle(u32),
+    // Stop when X changes to this value. Not while it stays there, or continuing
+    // would stop again on the very next cycle.
+    X(i32),
+    // Stop when the CRT lights this pixel, (row, column)
+    Pixel(usize, usize),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Cycle(c) => write!(f, "cycle {}", c),
+            Breakpoint::X(v) => write!(f, "x {}", v),
+            Breakpoint::Pixel(r, c) => write!(f, "pixel {} {}", r, c),
+        }
+    }
+}
+
+// Runs a program cycle by cycle and draws the CRT alongside
+struct Debugger {
+    cpu: Cpu<'static>,
+    crt: Crt,
+    breaks: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    fn new(program: Vec<Instr>) -> Self {
+        Debugger {
+            cpu: Cpu::new(program),
+            crt: Crt::default(),
+            breaks: Vec::new(),
+        }
+    }
+
+    // Run one cycle, with the breakpoint it hit if any. None once halted.
+    fn tick(&mut self) -> Option<Option<Breakpoint>> {
+        let (cycle, x) = (self.cpu.cycle + 1, self.cpu.x());
+        if !self.cpu.tick() {
+            return None;
+        }
+        self.crt.on_cycle(cycle, x);
+
+        let pos = (cycle - 1) as usize;
+        let lit = x.abs_diff((pos % 40) as i32) <= 1;
+        let hit = self.breaks.iter().copied().find(|b| match *b {
+            Breakpoint::Cycle(c) => c == cycle,
+            Breakpoint::X(v) => v == self.cpu.x() && v != x,
+            Breakpoint::Pixel(r, c) => lit && r * 40 + c == pos,
+        });
+        Some(hit)
+    }
+
+    // Tick until `done` says so, a breakpoint hits or the program ends
+    fn run_until(&mut self, mut done: impl FnMut(&Cpu) -> bool) -> String {
+        loop {
+            match self.tick() {
+                None => return "halted".to_string(),
+                Some(Some(b)) => return format!("breakpoint: {}", b),
+                Some(None) if done(&self.cpu) => return String::new(),
+                Some(None) => {}
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        let cpu = &self.cpu;
+        let regs = format!(
+            "cycle {} pc {} x {} a {} b {} c {} d {}",
+            cpu.cycle,
+            cpu.pc,
+            cpu.x(),
+            cpu.reg(Reg::A),
+            cpu.reg(Reg::B),
+            cpu.reg(Reg::C),
+            cpu.reg(Reg::D)
+        );
+        match cpu.program.get(cpu.pc) {
+            Some(i) if cpu.busy > 0 => format!("{} | {} ({}/{})", regs, i, cpu.busy, i.cycles()),
+            Some(i) => format!("{} | {}", regs, i),
+            None => format!("{} | halted", regs),
+        }
+    }
+
+    // The sprite against the row being drawn, ^ marks where the next pixel goes
+    fn sprite(&self) -> String {
+        let x = self.cpu.x();
+        let pos = self.cpu.cycle as usize;
+        let sprite: String = (0..40)
+            .map(|c| if x.abs_diff(c) <= 1 { '#' } else { '.' })
+            .collect();
+        let row = self.crt.rows.get(pos / 40).map_or("", |r| r.as_str());
+        format!(
+            "sprite {}\nrow {:<2} {}\n       {}^\n",
+            sprite,
+            pos / 40,
+            row,
+            " ".repeat(pos % 40)
+        )
+    }
+
+    // One command, false to quit
+    fn command(&mut self, line: &str, out: &mut impl Write) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let count = |n: Option<&&str>| -> Result<u32, String> {
+            match n.map_or(Ok(1), |n| n.parse()) {
+                Ok(0) | Err(_) => Err(format!("count has to be 1 or more, got {:?}", n.unwrap())),
+                Ok(n) => Ok(n),
+            }
+        };
+
+        let stop = match args[..] {
+            [] => return Ok(true),
+            ["quit"] | ["q"] => return Ok(false),
+            ["help"] => "tick [N], step [N], continue, break cycle N | x V | pixel ROW COL, \
+                 delete, info, sprite, screen, quit"
+                .to_string(),
+            ["tick" | "t", ref n @ ..] => {
+                let until = self.cpu.cycle + count(n.first())?;
+                self.run_until(|cpu| cpu.cycle >= until)
+            }
+            ["step" | "s", ref n @ ..] => {
+                let mut left = count(n.first())?;
+                self.run_until(|cpu| {
+                    if cpu.busy == 0 {
+                        left -= 1;
+                    }
+                    left == 0
+                })
+            }
+            ["continue" | "c"] => self.run_until(|_| false),
+            ["break" | "b", ref what @ ..] => {
+                let num = |s: &str| s.parse().map_err(|_| format!("bad number {:?}", s));
+                let b = match what {
+                    ["cycle", n] => Breakpoint::Cycle(num(n)?),
+                    ["x", v] => Breakpoint::X(v.parse().map_err(|_| format!("bad value {:?}", v))?),
+                    ["pixel", r, c] => Breakpoint::Pixel(num(r)? as usize, num(c)? as usize),
+                    _ => return Err("usage: break cycle N | x V | pixel ROW COL".to_string()),
+                };
+                self.breaks.push(b);
+                format!("breakpoint {}: {}", self.breaks.len(), b)
+            }
+            ["delete"] => {
+                self.breaks.clear();
+                "breakpoints cleared".to_string()
+            }
+            ["info" | "i"] => {
+                let mut s = self.status();
+                for (n, b) in self.breaks.iter().enumerate() {
+                    s += &format!("\nbreakpoint {}: {}", n + 1, b);
+                }
+                s
+            }
+            ["sprite"] => {
+                let sprite = self.sprite();
+                sprite.trim_end().to_string()
+            }
+            ["screen"] => self.crt.rows.join("\n"),
+            _ => return Err(format!("unknown command {:?}, try help", line)),
+        };
+
+        let running = matches!(args[0], "tick" | "t" | "step" | "s" | "continue" | "c");
+        let text = match running {
+            true if stop.is_empty() => self.status(),
+            true => format!("{}\n{}", stop, self.status()),
+            false => stop,
+        };
+        writeln!(out, "{}", text).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+// Run debugger commands until `quit` or the end of the input. The "(dbg)" prompt is
+// only printed when asked for, main turns it off unless stdin is a terminal.
+fn debug(
+    program: Vec<Instr>,
+    input: impl BufRead,
+    mut out: impl Write,
+    prompt: bool,
+) -> io::Result<()> {
+    let mut dbg = Debugger::new(program);
+    let mut lines = input.lines();
+
+    loop {
+        if prompt {
+            write!(out, "(dbg) ")?;
+            out.flush()?;
+        }
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        match dbg.command(&line?, &mut out) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => writeln!(out, "error: {}", e)?,
+        }
+    }
+}
+
+#[test]
+fn test_sample_program() {
+    let program = parse_program(TESTPROG).unwrap();
+    let mut signal = SignalStrength::default();
+    let mut crt = Crt::default();
+
+    let mut cpu = Cpu::new(program);
+    cpu.observe(&mut signal);
+    cpu.observe(&mut crt);
+    cpu.run();
+    assert_eq!(cpu.cycle, 240);
+    assert!(cpu.halted());
+    drop(cpu);
+
+    assert_eq!(signal.strengths, vec![420, 1140, 1800, 2940, 2880, 3960]);
+    assert_eq!(signal.total(), 13140);
+    assert_eq!(
+        crt.rows,
+        vec![
+            "##..##..##..##..##..##..##..##..##..##..",
+            "###...###...###...###...###...###...###.",
+            "####....####....####....####....####....",
+            "#####.....#####.....#####.....#####.....",
+            "######......######......######......####",
+            "#######.......#######.......#######.....",
+        ]
+    );
+}
+
+#[test]
+fn test_negative_strength() {
+    // X below zero gives a negative strength
+    let mut signal = SignalStrength::default();
+    let mut cpu = Cpu::new(parse_program(&format!("addx -5\n{}", "noop\n".repeat(20))).unwrap());
+    cpu.observe(&mut signal);
+    cpu.run();
+    drop(cpu);
+    assert_eq!(signal.strengths, vec![-80]);
+    assert_eq!(signal.total(), -80);
+}
+
+#[test]
+fn test_cycle_costs() {
+    let program = parse_program("noop\naddx 3\n\naddx -5").unwrap();
+    assert_eq!(
+        program,
+        vec![
+            Instr::Noop,
+            Instr::Addx(Val::Imm(3)),
+            Instr::Addx(Val::Imm(-5))
+        ]
+    );
+
+    let mut cpu = Cpu::new(program);
+    assert!(cpu.step());
+    assert_eq!((cpu.cycle, cpu.x()), (1, 1));
+    // addx is still running after its first cycle
+    assert!(cpu.tick());
+    assert_eq!((cpu.cycle, cpu.x()), (2, 1));
+    assert!(cpu.tick());
+    assert_eq!((cpu.cycle, cpu.x()), (3, 4));
+    assert!(cpu.step());
+    assert_eq!((cpu.cycle, cpu.x()), (5, -1));
+    assert!(!cpu.step());
+
+    assert_eq!(
+        parse_program("noop\naddx y").unwrap_err(),
+        "line 2: bad operand \"y\""
+    );
+    assert_eq!(
+        parse_program("jmp 3").unwrap_err(),
+        "line 1: unknown label \"3\""
+    );
+}
+
+#[test]
+fn test_read_crt() {
+    let screen = [
+        "###..###..####..##..###...##..####..##..",
+        "#..#.#..#....#.#..#.#..#.#..#....#.#..#.",
+        "#..#.###....#..#....#..#.#..#...#..#..#.",
+        "###..#..#..#...#.##.###..####..#...####.",
+        "#....#..#.#....#..#.#.#..#..#.#....#..#.",
+        "#....###..####..###.#..#.#..#.####.#..#.",
+    ];
+    let rows: Vec<String> = screen.iter().map(|r| r.to_string()).collect();
+    assert_eq!(
+        read_crt(&rows),
+        Reading {
+            text: "PBZGRAZA".to_string(),
+            unknown: vec![]
+        }
+    );
+
+    // Blank out the B, smudge the Z and light a spacer column next to the A
+    let mut rows = rows;
+    for r in rows.iter_mut() {
+        r.replace_range(5..9, "....");
+    }
+    rows[0].replace_range(10..11, ".");
+    rows[2].replace_range(29..30, "#");
+    assert_eq!(
+        read_crt(&rows),
+        Reading {
+            text: "P ?GR?ZA".to_string(),
+            unknown: vec![2, 5]
+        }
+    );
+}
+
+#[cfg(test)]
+fn trace_of(program: &[Instr]) -> Vec<i32> {
+    let mut trace = Trace::default();
+    let mut cpu = Cpu::new(program.to_vec());
+    cpu.observe(&mut trace);
+    cpu.run();
+    drop(cpu);
+    trace.xs
+}
+
+#[test]
+fn test_assembler() {
+    let src = "; count down in a
+    mov a 3
+loop:
+    addx 2      ; x += 2
+    add a -1
+    jnz a loop
+    mulx 2
+    subx b
+";
+    let program = parse_program(src).unwrap();
+    assert_eq!(program[3], Instr::Jnz(Reg::A, 1));
+
+    let mut cpu = Cpu::new(program.clone());
+    cpu.run();
+    assert_eq!((cpu.x(), cpu.reg(Reg::A), cpu.cycle), (14, 0, 24));
+
+    let text = disassemble(&program);
+    assert!(text.starts_with("    mov a 3     ; cycle 1\nL1:\n    addx 2      ; 2 cycles\n"));
+    assert!(text.ends_with("    mulx 2      ; 3 cycles\n    subx b      ; 2 cycles\n"));
+    assert_eq!(parse_program(&text).unwrap(), program);
+
+    // Lowered to addx/noop it runs the same, cycle for cycle
+    let lowered = lower(&program, 1000).unwrap();
+    assert!(lowered
+        .iter()
+        .all(|i| matches!(i, Instr::Noop | Instr::Addx(Val::Imm(_)))));
+    assert_eq!(trace_of(&lowered), trace_of(&program));
+
+    let sample = parse_program(TESTPROG).unwrap();
+    assert_eq!(trace_of(&lower(&sample, 1000).unwrap()), trace_of(&sample));
+
+    // Overflow wraps instead of panicking, and lowering wraps the same way
+    let big =
+        parse_program("noop\nmov x 100000\nmulx x\nnoop\nnoop\nmov a 2147483647\nadd a 1").unwrap();
+    let mut crt = Crt::default();
+    let mut cpu = Cpu::new(big.clone());
+    cpu.observe(&mut crt);
+    cpu.run();
+    assert_eq!(cpu.x(), 100000i32.wrapping_mul(100000));
+    assert_eq!(cpu.reg(Reg::A), i32::MIN);
+    drop(cpu);
+    assert_eq!(trace_of(&lower(&big, 1000).unwrap()), trace_of(&big));
+
+    assert_eq!(
+        lower(&parse_program("mov x 5\nmov x 6").unwrap(), 1000).unwrap_err(),
+        "X goes from 1 to 5 on cycle 2, addx can't change it that soon"
+    );
+    assert_eq!(
+        lower(&parse_program("l:\njmp l").unwrap(), 1000).unwrap_err(),
+        "still running after 1000 cycles"
+    );
+    assert_eq!(
+        parse_program("a:\nnoop").unwrap_err(),
+        "line 1: bad label \"a\""
+    );
+    assert_eq!(
+        parse_program("l:\nl:").unwrap_err(),
+        "line 2: label \"l\" defined twice"
+    );
+}
+
+#[test]
+fn test_draw_program() {
+    let screen = render_text("PBZGRAZA").unwrap();
+    assert_eq!(screen[0], "###..###..####..##..###...##..####..##..");
+    assert_eq!(screen[5], "#....###..####..###.#..#.#..#.####.#..#.");
+
+    for text in ["PBZGRAZA", "ELF", "BE HERE"] {
+        let rows = render_text(text).unwrap();
+        let program = draw_program(&rows).unwrap();
+        let mut crt = Crt::default();
+        let mut cpu = Cpu::new(program);
+        cpu.observe(&mut crt);
+        cpu.run();
+        assert_eq!(cpu.cycle, 240);
+        drop(cpu);
+        assert_eq!(read_crt(&crt.rows).text.trim_end(), text);
+    }
+
+    assert_eq!(
+        draw_program(&render_text("A").unwrap()).unwrap_err(),
+        "can't draw row 0 column 0, the first two pixels are always lit"
+    );
+    // Any other image works, X can cover every pair of pixels on its own
+    let mut seed: u32 = 12345;
+    let mut rows = vec![String::new(); 6];
+    for (i, row) in rows.iter_mut().enumerate() {
+        for j in 0..40 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            row.push(if (i == 0 && j < 2) || seed >> 30 == 0 {
+                '#'
+            } else {
+                '.'
+            });
+        }
+    }
+    assert!(draw_program(&rows).is_ok());
+
+    assert_eq!(render_text("E?").unwrap_err(), "no glyph for '?'");
+}
+
+#[test]
+fn test_debugger() {
+    let script = "break cycle 20
+continue
+info
+tick 0
+step 0
+tick 2
+step
+sprite
+delete
+b pixel 1 2
+c
+s 3
+break x -1
+c
+jump
+b x one
+continue
+";
+    let mut out = Vec::new();
+    debug(
+        parse_program(TESTPROG).unwrap(),
+        script.as_bytes(),
+        &mut out,
+        false,
+    )
+    .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        out.lines().collect::<Vec<&str>>(),
+        vec![
+            "breakpoint 1: cycle 20",
+            "breakpoint: cycle 20",
+            "cycle 20 pc 10 x 21 a 0 b 0 c 0 d 0 | addx -1 (1/2)",
+            "cycle 20 pc 10 x 21 a 0 b 0 c 0 d 0 | addx -1 (1/2)",
+            "breakpoint 1: cycle 20",
+            "error: count has to be 1 or more, got \"0\"",
+            "error: count has to be 1 or more, got \"0\"",
+            "cycle 22 pc 11 x 20 a 0 b 0 c 0 d 0 | addx 5 (1/2)",
+            "cycle 23 pc 12 x 25 a 0 b 0 c 0 d 0 | addx -1",
+            "sprite ........................###.............",
+            "row 0  ##..##..##..##..##..##..................",
+            "                              ^",
+            "breakpoints cleared",
+            "breakpoint 1: pixel 1 2",
+            "breakpoint: pixel 1 2",
+            "cycle 43 pc 22 x 26 a 0 b 0 c 0 d 0 | addx -19",
+            "cycle 49 pc 25 x 24 a 0 b 0 c 0 d 0 | addx -11",
+            "breakpoint 2: x -1",
+            "breakpoint: x -1",
+            "cycle 209 pc 127 x -1 a 0 b 0 c 0 d 0 | addx 22",
+            "error: unknown command \"jump\", try help",
+            "error: bad value \"one\"",
+            "halted",
+            "cycle 240 pc 146 x 17 a 0 b 0 c 0 d 0 | halted",
+        ]
+    );
+}
+
+// day10 [run FILE | asm FILE | disasm FILE | debug FILE | draw TEXT | draw-image FILE]
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let image = match args.first().map(|a| a.as_str()) {
+        Some("draw") => Some(render_text(args.get(1).ok_or("missing text")?)?),
+        Some("draw-image") => Some(
+            fs::read_to_string(args.get(1).ok_or("missing image file")?)?
+                .lines()
+                .filter(|l| !l.is_empty())
+                .map(|l| l.to_string())
+                .collect(),
+        ),
+        _ => None,
+    };
+    if let Some(rows) = image {
+        for instr in draw_program(&rows)? {
+            println!("{}", instr);
+        }
+        return Ok(());
+    }
+    let cmd = args.first().map_or("run", |a| a.as_str());
+    if !["run", "asm", "debug", "disasm"].contains(&cmd) {
+        return Err(format!("unknown command {}", cmd).into());
+    }
+    let path = match args.len() {
+        0 => "src/puzzle.txt",
+        _ => args.get(1).ok_or("missing program file")?,
+    };
+    let contents = fs::read_to_string(path)?;
+    let program = parse_program(&contents)?;
+
+    match cmd {
+        "asm" => {
+            for instr in lower(&program, 1_000_000)? {
+                println!("{}", instr);
+            }
+            return Ok(());
+        }
+        "debug" => {
+            let stdin = io::stdin();
+            let prompt = stdin.is_terminal();
+            debug(program, stdin.lock(), io::stdout().lock(), prompt)?;
+            return Ok(());
+        }
+        "disasm" => {
+            print!("{}", disassemble(&program));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let mut signal = SignalStrength::default();
+    let mut crt = Crt::default();
+
+    let mut cpu = Cpu::new(program);
+    cpu.observe(&mut signal);
+    cpu.observe(&mut crt);
+    cpu.run();
+    drop(cpu);
 
     // Part 1
-    for row in crt {
+    println!("part 1: {:?}", signal.total());
+
+    // Part 2
+    for row in crt.rows.iter() {
         println!("{}", row);
     }
+    let reading = read_crt(&crt.rows);
+    println!("part 2: {}", reading.text);
+    if !reading.unknown.is_empty() {
+        eprintln!("unrecognised letters at {:?}", reading.unknown);
+    }
     Ok(())
 }
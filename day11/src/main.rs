@@ -1,9 +1,493 @@
-fn main() {
-    let contents = include_str!("test.txt");
-    let valid = "123456789-,";
-    let sensor_beacons = contents
-        .lines()
-        .map(|line| line.chars().filter(|c| valid.contains(c)))
-        .collect::<Vec<&str>>();
-    eprintln!("sensor_beacons = {:?}", sensor_beacons);
+use std::error::Error;
+use std::fs;
+
+#[cfg(test)]
+const TESTMONKEYS: &str = include_str!("test.txt");
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Token {
+    Num(u64),
+    Old,
+    Op(char),
+    Open,
+    Close,
+}
+
+// Tokens with the column they start at, counting from 1
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        i += 1;
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '+' | '-' | '*' | '/' | '%' => Token::Op(c),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            _ if c.is_ascii_alphanumeric() || c == '_' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.as_str() {
+                    "old" => Token::Old,
+                    _ if c.is_ascii_digit() => {
+                        Token::Num(word.parse().map_err(|_| {
+                            format!("bad number {:?} at column {}", word, start + 1)
+                        })?)
+                    }
+                    _ => return Err(format!("unknown name {:?} at column {}", word, start + 1)),
+                }
+            }
+            _ => return Err(format!("unexpected {:?} at column {}", c, start + 1)),
+        };
+        tokens.push((token, start + 1));
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Code {
+    Old,
+    Num(u64),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// An operation compiled to postfix code for a little stack machine
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    code: Vec<Code>,
+}
+
+// Recursive descent, * / % bind tighter than + -, all left to right
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    code: Vec<Code>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).map(|t| t.0)
+    }
+
+    fn here(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some((_, col)) => format!("at column {}", col),
+            None => "at the end".to_string(),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[char],
+        operand: fn(&mut Parser) -> Result<(), String>,
+    ) -> Result<(), String> {
+        operand(self)?;
+        while let Some(Token::Op(c)) = self.peek() {
+            if !ops.contains(&c) {
+                break;
+            }
+            self.pos += 1;
+            operand(self)?;
+            self.code.push(match c {
+                '+' => Code::Add,
+                '-' => Code::Sub,
+                '*' => Code::Mul,
+                '/' => Code::Div,
+                _ => Code::Rem,
+            });
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<(), String> {
+        self.binary(&['+', '-'], Parser::term)
+    }
+
+    fn term(&mut self) -> Result<(), String> {
+        self.binary(&['*', '/', '%'], Parser::factor)
+    }
+
+    fn factor(&mut self) -> Result<(), String> {
+        let Some(&(token, col)) = self.tokens.get(self.pos) else {
+            return Err("expected a number, old or ( at the end".to_string());
+        };
+        self.pos += 1;
+        match token {
+            Token::Num(n) => self.code.push(Code::Num(n)),
+            Token::Old => self.code.push(Code::Old),
+            Token::Open => {
+                self.expr()?;
+                if self.peek() != Some(Token::Close) {
+                    return Err(format!(
+                        "missing ) for ( at column {}, {}",
+                        col,
+                        self.here()
+                    ));
+                }
+                self.pos += 1;
+            }
+            _ => return Err(format!("expected a number, old or ( at column {}", col)),
+        }
+        Ok(())
+    }
+}
+
+impl Expr {
+    fn compile(src: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+            code: Vec::new(),
+        };
+        parser.expr()?;
+        if let Some(&(token, col)) = parser.tokens.get(parser.pos) {
+            return Err(match token {
+                Token::Close => format!("unmatched ) at column {}", col),
+                _ => format!("expected an operator at column {}", col),
+            });
+        }
+        Ok(Expr { code: parser.code })
+    }
+
+    // Whether the result modulo m only depends on old modulo m, no / or %
+    fn modular(&self) -> bool {
+        !self.code.iter().any(|c| matches!(c, Code::Div | Code::Rem))
+    }
+
+    // With a modulus every step is taken modulo it, which needs a modular expression.
+    // Without one, overflow, going below zero and dividing by zero are errors.
+    fn eval(&self, old: u64, modulus: Option<u64>) -> Result<u64, String> {
+        let mut stack: Vec<u64> = Vec::with_capacity(self.code.len());
+
+        for code in &self.code {
+            let v = match (code, modulus) {
+                (Code::Old, m) => m.map_or(old, |m| old % m),
+                (Code::Num(n), m) => m.map_or(*n, |m| n % m),
+                (op, m) => {
+                    let (Some(b), Some(a)) = (stack.pop(), stack.pop()) else {
+                        unreachable!("compiled code is balanced");
+                    };
+                    match (op, m) {
+                        (Code::Add, Some(m)) => ((a as u128 + b as u128) % m as u128) as u64,
+                        (Code::Sub, Some(m)) => {
+                            ((a as u128 + m as u128 - b as u128) % m as u128) as u64
+                        }
+                        (Code::Mul, Some(m)) => ((a as u128 * b as u128) % m as u128) as u64,
+                        (Code::Div | Code::Rem, Some(_)) => {
+                            return Err("/ and % don't work modulo".to_string())
+                        }
+                        (Code::Add, None) => a.checked_add(b).ok_or("worry overflowed")?,
+                        (Code::Sub, None) => a.checked_sub(b).ok_or("worry went below zero")?,
+                        (Code::Mul, None) => a.checked_mul(b).ok_or("worry overflowed")?,
+                        (Code::Div, None) => a.checked_div(b).ok_or("division by zero")?,
+                        (Code::Rem, None) => a.checked_rem(b).ok_or("division by zero")?,
+                        (Code::Old | Code::Num(_), _) => unreachable!(),
+                    }
+                }
+            };
+            stack.push(v);
+        }
+
+        Ok(stack.pop().unwrap_or(old))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Monkey {
+    items: Vec<u64>,
+    // new = op
+    op: Expr,
+    divisor: u64,
+    if_true: usize,
+    if_false: usize,
+    inspected: u64,
+}
+
+impl Monkey {
+    // One "Monkey N:" block, without the header line
+    fn parse(lines: &[&str]) -> Result<Monkey, String> {
+        let field = |name: &str| {
+            lines
+                .iter()
+                .find_map(|l| l.trim().strip_prefix(name))
+                .map(|v| v.trim())
+                .ok_or(format!("missing {:?}", name))
+        };
+        let num = |name: &str, v: &str| {
+            v.parse()
+                .map_err(|_| format!("bad number {:?} in {:?}", v, name))
+        };
+        let last_num = |name: &str| -> Result<u64, String> {
+            let v = field(name)?;
+            num(name, v.rsplit(' ').next().unwrap_or(v))
+        };
+
+        let items = field("Starting items:")?;
+        let items = match items {
+            "" => Vec::new(),
+            _ => items
+                .split(',')
+                .map(|i| num("Starting items:", i.trim()))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let divisor = last_num("Test: divisible by")?;
+        if divisor == 0 {
+            return Err("can't test divisibility by 0".to_string());
+        }
+
+        Ok(Monkey {
+            items,
+            op: Expr::compile(
+                field("Operation:")?
+                    .strip_prefix("new =")
+                    .ok_or("operation has to start with \"new =\"")?
+                    .trim(),
+            )
+            .map_err(|e| format!("operation: {}", e))?,
+            divisor,
+            if_true: last_num("If true: throw to monkey")? as usize,
+            if_false: last_num("If false: throw to monkey")? as usize,
+            inspected: 0,
+        })
+    }
+}
+
+// Monkeys have to come in order from 0 and only throw to each other
+fn parse_monkeys(src: &str) -> Result<Vec<Monkey>, String> {
+    let lines: Vec<&str> = src.lines().collect();
+    let mut monkeys = Vec::new();
+
+    for block in lines.split(|l| l.trim().is_empty()) {
+        let Some((header, rest)) = block.split_first() else {
+            continue;
+        };
+        let n = monkeys.len();
+        if header.trim() != format!("Monkey {}:", n) {
+            return Err(format!("expected \"Monkey {}:\", got {:?}", n, header));
+        }
+        monkeys.push(Monkey::parse(rest).map_err(|e| format!("monkey {}: {}", n, e))?);
+    }
+
+    for (n, m) in monkeys.iter().enumerate() {
+        for target in [m.if_true, m.if_false] {
+            if target >= monkeys.len() || target == n {
+                return Err(format!("monkey {}: can't throw to monkey {}", n, target));
+            }
+        }
+    }
+
+    Ok(monkeys)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Least common multiple of all divisors, None if it doesn't fit in a u64
+fn divisors_lcm(monkeys: &[Monkey]) -> Option<u64> {
+    monkeys.iter().try_fold(1u64, |acc, m| {
+        (acc / gcd(acc, m.divisor)).checked_mul(m.divisor)
+    })
+}
+
+// With relief worry drops to a third after each inspection. Without it worry is kept
+// modulo the least common multiple of all divisors, which leaves every test's outcome
+// alone but only works for operations without / and %.
+fn play(monkeys: &mut [Monkey], rounds: usize, relief: bool) -> Result<(), String> {
+    let modulus = match relief {
+        true => None,
+        false => Some(divisors_lcm(monkeys).ok_or("the divisors' common multiple overflows")?),
+    };
+    if let (Some(_), Some(n)) = (modulus, monkeys.iter().position(|m| !m.op.modular())) {
+        return Err(format!(
+            "monkey {}: worry can't be kept small with / or % in the operation",
+            n
+        ));
+    }
+
+    for _ in 0..rounds {
+        for n in 0..monkeys.len() {
+            let items = std::mem::take(&mut monkeys[n].items);
+            monkeys[n].inspected += items.len() as u64;
+
+            for item in items {
+                let m = &monkeys[n];
+                let mut worry =
+                    m.op.eval(item, modulus)
+                        .map_err(|e| format!("monkey {}: {}", n, e))?;
+                if relief {
+                    worry /= 3;
+                }
+
+                let target = if worry.is_multiple_of(m.divisor) {
+                    m.if_true
+                } else {
+                    m.if_false
+                };
+                monkeys[target].items.push(worry);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The two busiest monkeys' inspection counts multiplied
+fn monkey_business(monkeys: &[Monkey]) -> u64 {
+    let mut counts: Vec<u64> = monkeys.iter().map(|m| m.inspected).collect();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    counts.iter().take(2).product()
+}
+
+fn part1(monkeys: &[Monkey]) -> Result<u64, String> {
+    let mut monkeys = monkeys.to_vec();
+    play(&mut monkeys, 20, true)?;
+    Ok(monkey_business(&monkeys))
+}
+
+fn part2(monkeys: &[Monkey]) -> Result<u64, String> {
+    let mut monkeys = monkeys.to_vec();
+    play(&mut monkeys, 10000, false)?;
+    Ok(monkey_business(&monkeys))
+}
+
+#[test]
+fn test_sample() {
+    let monkeys = parse_monkeys(TESTMONKEYS).unwrap();
+    assert_eq!(monkeys.len(), 4);
+    assert_eq!(monkeys[2].op.code, vec![Code::Old, Code::Old, Code::Mul]);
+    assert_eq!(monkeys[1].items, vec![54, 65, 75, 74]);
+    assert_eq!((monkeys[0].if_true, monkeys[0].if_false), (2, 3));
+
+    let mut after = monkeys.clone();
+    play(&mut after, 20, true).unwrap();
+    let inspected: Vec<u64> = after.iter().map(|m| m.inspected).collect();
+    assert_eq!(inspected, vec![101, 95, 7, 105]);
+
+    assert_eq!(part1(&monkeys), Ok(10605));
+    assert_eq!(part2(&monkeys), Ok(2713310158));
+
+    // Shared factors only count once
+    let shared = TESTMONKEYS.replace("by 13", "by 46");
+    assert_eq!(
+        divisors_lcm(&parse_monkeys(&shared).unwrap()),
+        Some(46 * 19 * 17)
+    );
+
+    let huge = TESTMONKEYS
+        .replace("by 23", "by 4294967297")
+        .replace("by 19", "by 4294967295");
+    assert_eq!(
+        part2(&parse_monkeys(&huge).unwrap()),
+        Err("the divisors' common multiple overflows".to_string())
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(
+        parse_monkeys("Monkey 1:").unwrap_err(),
+        "expected \"Monkey 0:\", got \"Monkey 1:\""
+    );
+
+    let bad_op = TESTMONKEYS.replace("old + 3", "old ^ 3");
+    assert_eq!(
+        parse_monkeys(&bad_op).unwrap_err(),
+        "monkey 3: operation: unexpected '^' at column 5"
+    );
+
+    let bad_target = TESTMONKEYS.replace(
+        "throw to monkey 3\n\nMonkey 1",
+        "throw to monkey 9\n\nMonkey 1",
+    );
+    assert_eq!(
+        parse_monkeys(&bad_target).unwrap_err(),
+        "monkey 0: can't throw to monkey 9"
+    );
+
+    let no_test = TESTMONKEYS.replace("  Test: divisible by 17\n", "");
+    assert_eq!(
+        parse_monkeys(&no_test).unwrap_err(),
+        "monkey 3: missing \"Test: divisible by\""
+    );
+}
+
+#[test]
+fn test_expressions() {
+    let eval = |src: &str, old: u64| Expr::compile(src).and_then(|e| e.eval(old, None));
+    assert_eq!(eval("old * old", 7), Ok(49));
+    assert_eq!(eval("1 + 2 * 3 - 4", 0), Ok(3));
+    assert_eq!(eval("(1 + 2) * (3 - old)", 1), Ok(6));
+    assert_eq!(eval("100 / 7 % 4 * old", 2), Ok(4));
+    assert_eq!(eval("10 - 3 - 2", 0), Ok(5));
+    assert_eq!(eval("((old))", 12), Ok(12));
+
+    assert_eq!(eval("old - 5", 3), Err("worry went below zero".to_string()));
+    assert_eq!(
+        eval("old / (old - 2)", 2),
+        Err("division by zero".to_string())
+    );
+    assert_eq!(
+        eval("old * 99999999999", 999999999999),
+        Err("worry overflowed".to_string())
+    );
+
+    // Modulo 7 the result only depends on old modulo 7
+    let e = Expr::compile("(old - 10) * old + 3").unwrap();
+    assert!(e.modular());
+    assert_eq!(e.eval(24, Some(7)), Ok(((24 - 10) * 24 + 3) % 7));
+    assert!(!Expr::compile("old % 3").unwrap().modular());
+
+    let err = |src: &str| Expr::compile(src).unwrap_err();
+    assert_eq!(err("old ** 2"), "expected a number, old or ( at column 6");
+    assert_eq!(err("old +"), "expected a number, old or ( at the end");
+    assert_eq!(err("(old + 1"), "missing ) for ( at column 1, at the end");
+    assert_eq!(err("old + 1)"), "unmatched ) at column 8");
+    assert_eq!(err("old 2"), "expected an operator at column 5");
+    assert_eq!(err("new + 1"), "unknown name \"new\" at column 1");
+    assert_eq!(err("2 ^ old"), "unexpected '^' at column 3");
+    assert_eq!(err(""), "expected a number, old or ( at the end");
+
+    // Richer operations in a puzzle
+    let monkeys = parse_monkeys(&TESTMONKEYS.replace("old + 3", "(old + 6) - 3")).unwrap();
+    assert_eq!(part1(&monkeys), Ok(10605));
+    assert_eq!(part2(&monkeys), Ok(2713310158));
+
+    let halving = parse_monkeys(&TESTMONKEYS.replace("old + 3", "old / 2 + 3")).unwrap();
+    assert!(part1(&halving).is_ok());
+    assert_eq!(
+        part2(&halving),
+        Err("monkey 3: worry can't be kept small with / or % in the operation".to_string())
+    );
+
+    let no_new = TESTMONKEYS.replace("new = old + 3", "old + 3");
+    assert_eq!(
+        parse_monkeys(&no_new).unwrap_err(),
+        "monkey 3: operation has to start with \"new =\""
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string("src/puzzle.txt")?;
+    let monkeys = parse_monkeys(&contents)?;
+
+    println!("part 1: {}", part1(&monkeys)?);
+    println!("part 2: {}", part2(&monkeys)?);
+    Ok(())
 }
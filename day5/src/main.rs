@@ -1,8 +1,14 @@
+use itertools::Itertools;
 use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::path::Path;
 
-const STACKS: &str = &"    [D]    
+#[cfg(test)]
+const STACKS: &str = "    [D]    
 [N] [C]    
 [Z] [M] [P]
  1   2   3 
@@ -12,7 +18,7 @@ move 3 from 1 to 3
 move 2 from 2 to 1
 move 1 from 1 to 2";
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 struct Instr {
     count: usize,
     from_stack: usize,
@@ -32,7 +38,7 @@ impl Instr {
     }
 
     // keep_order = false for p1, keep_order = true for p2
-    fn execute(&self, stacks: &mut Vec<String>, keep_order: bool) {
+    fn execute(&self, stacks: &mut [String], keep_order: bool) {
         let fstack = &stacks[self.from_stack];
         let range = fstack.len() - self.count..;
         let drained: &Vec<char> = &stacks[self.from_stack].drain(range).collect::<Vec<char>>();
@@ -49,11 +55,24 @@ impl Instr {
     }
 }
 
+// Print in the puzzle's own format so a plan can be fed straight back into the simulator
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "move {} from {} to {}",
+            self.count,
+            self.from_stack + 1,
+            self.to_stack + 1
+        )
+    }
+}
+
 // Transpose string rows into columns to make it easier to parse the stacks
 fn transpose(v: Vec<&str>) -> Vec<String> {
     let mut new_rows: Vec<String> = Vec::new();
 
-    for (_, row) in v.iter().enumerate() {
+    for row in v.iter() {
         for (j, col) in row.chars().enumerate() {
             if new_rows.len() <= j {
                 new_rows.push("".to_string())
@@ -77,7 +96,7 @@ fn clean_rows(v: Vec<String>) -> Vec<String> {
     let mut cleaned: Vec<String> = Vec::new();
 
     for row in replaced {
-        if row.len() > 0 {
+        if !row.is_empty() {
             cleaned.push(row)
         }
     }
@@ -88,16 +107,241 @@ fn clean_rows(v: Vec<String>) -> Vec<String> {
         .collect()
 }
 
+// Load and clean a stack drawing on its own, without any instructions
+fn load_stacks(drawing: &str) -> Vec<String> {
+    let rows = drawing.split('\n').collect::<Vec<&str>>();
+    let res = transpose(rows);
+    clean_rows(res)
+}
+
 // Load, clean, and prep the stacks and instructions
 fn load_stack_and_instr(contents: &str) -> (Vec<String>, Vec<Instr>) {
     let stack_inst: Vec<&str> = contents.split("\n\n").collect();
-    let rows = stack_inst[0].split("\n").collect::<Vec<&str>>();
-    let res = transpose(rows);
-    let cleaned = clean_rows(res);
-    let inst_str = stack_inst[1].split("\n").collect::<Vec<&str>>();
-    let instrs: Vec<Instr> = inst_str.iter().map(|s| Instr::parse(*s)).collect();
+    let cleaned = load_stacks(stack_inst[0]);
+    let inst_str = stack_inst[1].split('\n').collect::<Vec<&str>>();
+    let instrs: Vec<Instr> = inst_str.iter().map(|s| Instr::parse(s)).collect();
+
+    (cleaned, instrs)
+}
+
+// The two crane models from the puzzle: the 9000 moves crates one at a time (p1),
+// the 9001 picks up the whole group and keeps its order (p2)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Crane {
+    CrateMover9000,
+    CrateMover9001,
+}
+
+impl Crane {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "9000" => Some(Crane::CrateMover9000),
+            "9001" => Some(Crane::CrateMover9001),
+            _ => None,
+        }
+    }
 
-    return (cleaned, instrs);
+    fn keep_order(&self) -> bool {
+        *self == Crane::CrateMover9001
+    }
+}
+
+// What the planner is asked to reach: either every stack exactly, or just the top row
+#[derive(Debug)]
+enum Target {
+    Full(Vec<String>),
+    Tops(String),
+}
+
+impl Target {
+    fn matches(&self, stacks: &[String]) -> bool {
+        match self {
+            Target::Full(want) => stacks == want.as_slice(),
+            Target::Tops(want) => {
+                want.chars().count() == stacks.len()
+                    && stacks
+                        .iter()
+                        .zip(want.chars())
+                        .all(|(stack, c)| stack.ends_with(c))
+            }
+        }
+    }
+
+    // Lower bound on the moves still needed. Every move touches exactly two stacks and
+    // takes crates off exactly one of them, so this never overestimates and A* stays
+    // optimal.
+    fn min_moves_left(&self, stacks: &[String]) -> usize {
+        match self {
+            Target::Full(want) => {
+                let mut touched: usize = 0;
+                let mut drained = 0;
+
+                for (have, want) in stacks.iter().zip(want.iter()) {
+                    if have != want {
+                        touched += 1;
+                    }
+                    if !want.starts_with(have.as_str()) {
+                        drained += 1;
+                    }
+                }
+
+                drained.max(touched.div_ceil(2))
+            }
+            Target::Tops(want) => {
+                let wrong = stacks
+                    .iter()
+                    .zip(want.chars())
+                    .filter(|(stack, c)| !stack.ends_with(*c))
+                    .count();
+
+                wrong.div_ceil(2)
+            }
+        }
+    }
+
+    // Cheap check that the target can be reached at all, so we don't search forever
+    fn reachable_from(&self, stacks: &[String]) -> bool {
+        match self {
+            Target::Full(want) => {
+                let mut have: Vec<char> = stacks.iter().flat_map(|s| s.chars()).collect();
+                let mut need: Vec<char> = want.iter().flat_map(|s| s.chars()).collect();
+                have.sort_unstable();
+                need.sort_unstable();
+
+                want.len() == stacks.len() && have == need
+            }
+            Target::Tops(want) => {
+                let have = stacks.iter().flat_map(|s| s.chars()).counts();
+
+                want.chars().count() == stacks.len()
+                    && want
+                        .chars()
+                        .counts()
+                        .iter()
+                        .all(|(c, n)| have.get(c).is_some_and(|h| h >= n))
+            }
+        }
+    }
+}
+
+// Run a program from a starting drawing, the same way part1/part2 do
+fn replay(stacks: &[String], program: &[Instr], crane: Crane) -> Vec<String> {
+    let mut stacks = stacks.to_vec();
+
+    for inst in program {
+        inst.execute(&mut stacks, crane.keep_order());
+    }
+
+    stacks
+}
+
+// Why `plan` came back without a program
+#[derive(Debug, PartialEq)]
+enum PlanError {
+    // The target has the wrong width or crates the stacks don't have
+    Unreachable,
+    // Stopped after seeing this many distinct states
+    Budget(usize),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::Unreachable => write!(f, "the target can't be reached from these stacks"),
+            PlanError::Budget(n) => write!(f, "gave up after {} states, raise --max-states", n),
+        }
+    }
+}
+
+// Search backwards from the simulator: A* over stack states for the shortest program that
+// turns `stacks` into `target` with the given crane. Each state is kept only as a key of
+// `best`; a node just records its parent and instruction, and the state is rebuilt by
+// replaying the path with Instr::execute when it comes off the queue.
+fn plan(
+    stacks: &[String],
+    target: &Target,
+    crane: Crane,
+    max_states: usize,
+) -> Result<Vec<Instr>, PlanError> {
+    if !target.reachable_from(stacks) {
+        return Err(PlanError::Unreachable);
+    }
+
+    // (parent index, instruction that produced it), the start is its own parent
+    let mut nodes: Vec<(usize, Option<Instr>)> = vec![(0, None)];
+    // Every state seen, to the node with the cheapest path found so far. The stacks are
+    // joined into one string, crates are single letters so a space can't be ambiguous.
+    let mut best: HashMap<String, usize> = HashMap::new();
+    let mut open = BinaryHeap::new();
+
+    best.insert(stacks.join(" "), 0);
+    open.push(Reverse((target.min_moves_left(stacks), 0, 0)));
+
+    let path = |nodes: &[(usize, Option<Instr>)], mut cur: usize| {
+        let mut program = Vec::new();
+        while let Some(inst) = nodes[cur].1.clone() {
+            program.push(inst);
+            cur = nodes[cur].0;
+        }
+        program.reverse();
+        program
+    };
+    let depth = |nodes: &[(usize, Option<Instr>)], mut cur: usize| {
+        let mut moves = 0;
+        while nodes[cur].1.is_some() {
+            moves += 1;
+            cur = nodes[cur].0;
+        }
+        moves
+    };
+
+    while let Some(Reverse((_, cost, idx))) = open.pop() {
+        let program = path(&nodes, idx);
+        let state = replay(stacks, &program, crane);
+
+        // A cheaper path to this state was queued after this one
+        if best.get(&state.join(" ")) != Some(&idx) {
+            continue;
+        }
+
+        if target.matches(&state) {
+            return Ok(program);
+        }
+
+        for from_stack in 0..state.len() {
+            for to_stack in 0..state.len() {
+                if from_stack == to_stack {
+                    continue;
+                }
+
+                for count in 1..=state[from_stack].len() {
+                    let inst = Instr {
+                        count,
+                        from_stack,
+                        to_stack,
+                    };
+                    let mut next = state.clone();
+                    inst.execute(&mut next, crane.keep_order());
+
+                    let key = next.join(" ");
+                    let seen = best.get(&key).map(|&n| depth(&nodes, n));
+                    if seen.is_some_and(|c| c <= cost + 1) {
+                        continue;
+                    }
+                    if seen.is_none() && best.len() >= max_states {
+                        return Err(PlanError::Budget(max_states));
+                    }
+
+                    let estimate = cost + 1 + target.min_moves_left(&next);
+                    nodes.push((idx, Some(inst)));
+                    best.insert(key, nodes.len() - 1);
+                    open.push(Reverse((estimate, cost + 1, nodes.len() - 1)));
+                }
+            }
+        }
+    }
+
+    Err(PlanError::Unreachable)
 }
 
 #[test]
@@ -137,6 +381,61 @@ fn test_sample_input_part1() {
     assert_eq!(top, "CMZ");
 }
 
+#[test]
+fn test_plan_sample_full_target() {
+    let (stacks, instrs) = load_stack_and_instr(STACKS);
+    let want = replay(&stacks, &instrs, Crane::CrateMover9000);
+    let target = Target::Full(want.clone());
+
+    let program = plan(&stacks, &target, Crane::CrateMover9000, 100_000).unwrap();
+
+    assert!(program.len() <= instrs.len());
+    assert_eq!(replay(&stacks, &program, Crane::CrateMover9000), want);
+}
+
+#[test]
+fn test_plan_sample_tops() {
+    let (stacks, _) = load_stack_and_instr(STACKS);
+
+    for (crane, tops) in [
+        (Crane::CrateMover9000, "CMZ"),
+        (Crane::CrateMover9001, "MCD"),
+    ] {
+        let target = Target::Tops(tops.to_string());
+        let program = plan(&stacks, &target, crane, 100_000).unwrap();
+        let text = program.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+
+        // The printed program must parse back into the same instructions
+        let reparsed: Vec<Instr> = text.iter().map(|s| Instr::parse(s)).collect();
+        assert_eq!(reparsed, program);
+        assert!(target.matches(&replay(&stacks, &program, crane)));
+    }
+}
+
+#[test]
+fn test_plan_unreachable() {
+    let (stacks, _) = load_stack_and_instr(STACKS);
+
+    let missing = Target::Tops("XYZ".to_string());
+    assert_eq!(
+        plan(&stacks, &missing, Crane::CrateMover9000, 100_000),
+        Err(PlanError::Unreachable)
+    );
+
+    let wrong_width = Target::Full(vec!["ZNDCMP".to_string()]);
+    assert_eq!(
+        plan(&stacks, &wrong_width, Crane::CrateMover9000, 100_000),
+        Err(PlanError::Unreachable)
+    );
+
+    // Reachable, but not within 10 states
+    let tops = Target::Tops("CMZ".to_string());
+    assert_eq!(
+        plan(&stacks, &tops, Crane::CrateMover9000, 10),
+        Err(PlanError::Budget(10))
+    );
+}
+
 fn part1() -> Result<(), Box<dyn Error>> {
     let contents = fs::read_to_string("src/puzzle.txt")?;
 
@@ -173,7 +472,56 @@ fn part2() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// day5 plan <input> <target> [9000|9001] [--max-states N]
+// <target> is either a drawing file with the wanted stacks or a literal top row like CMZ
+fn plan_cmd(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: day5 plan <input> <target> [9000|9001] [--max-states N]";
+    let mut args = args.to_vec();
+    let mut max_states = 5_000_000;
+    if let Some(i) = args.iter().position(|a| a == "--max-states") {
+        let n = args.get(i + 1).ok_or(usage)?;
+        max_states = n
+            .parse()
+            .map_err(|_| format!("--max-states wants a number, got {:?}", n))?;
+        args.drain(i..=i + 1);
+    }
+    if args.len() < 2 {
+        return Err(usage.into());
+    }
+
+    let contents = fs::read_to_string(&args[0])?;
+    let stacks = load_stacks(contents.split("\n\n").next().unwrap_or_default());
+
+    let target = if Path::new(&args[1]).is_file() {
+        Target::Full(load_stacks(
+            fs::read_to_string(&args[1])?.trim_end_matches('\n'),
+        ))
+    } else {
+        Target::Tops(args[1].clone())
+    };
+
+    let crane_arg = args.get(2).map_or("9000", |s| s.as_str());
+    let crane = Crane::parse(crane_arg).ok_or(format!("unknown crane model {}", crane_arg))?;
+
+    match plan(&stacks, &target, crane, max_states) {
+        Ok(program) => {
+            eprintln!("Plan -- {} moves", program.len());
+            for inst in program {
+                println!("{}", inst);
+            }
+            Ok(())
+        }
+        Err(e) => Err(format!("no plan found: {}", e).into()),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(|s| s.as_str()) == Some("plan") {
+        return plan_cmd(&args[1..]);
+    }
+
     part1()?;
     part2()?;
     Ok(())
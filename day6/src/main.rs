@@ -1,110 +1,442 @@
 use bit_set::BitSet;
 use itertools::Itertools;
+use serde_json::json;
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Bytes, Read};
+use std::num::NonZeroUsize;
 
-const TESTCOMMS: &str = &"bvwbjplbgvbhsrlpgdmjqwftvncz
+#[cfg(test)]
+const TESTCOMMS: &str = "bvwbjplbgvbhsrlpgdmjqwftvncz
 nppdvjthqldpwncqszvftbrmjlhg
 nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg
 zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw";
 
-const TESTCOMMS2: &str = &"mjqjpqmgbljsphdztnvjfqwrcgsmlb
+#[cfg(test)]
+const TESTCOMMS2: &str = "mjqjpqmgbljsphdztnvjfqwrcgsmlb
 bvwbjplbgvbhsrlpgdmjqwftvncz
 nppdvjthqldpwncqszvftbrmjlhg
 nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg
 zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw";
 
-fn find_unique_window_pos(winsize: usize, comm: &str) -> i32 {
-    let mut unique_pos: i32 = -1;
+// Every finder returns the 1-based end of the first window with no repeated bytes, or
+// None if the stream has no marker
+fn find_unique_window_pos(winsize: usize, comm: &[u8]) -> Option<usize> {
+    for i in winsize..=comm.len() {
+        let window = &comm[i - winsize..i];
+        let mut uniq: Vec<u8> = Vec::new();
 
-    for (i, _) in comm.chars().collect::<Vec<char>>().iter().enumerate() {
-        if i > winsize - 1 {
-            let window = &comm[i - winsize..i];
-            let mut uniq: Vec<char> = Vec::new();
-            let mut _isunique = false;
-
-            for wc in window.chars() {
-                if !uniq.contains(&wc) {
-                    uniq.push(wc);
-                }
+        for wc in window {
+            if !uniq.contains(wc) {
+                uniq.push(*wc);
             }
+        }
 
-            if uniq.len() == winsize {
-                _isunique = true;
-                break;
-            }
-            unique_pos = (i + 1) as i32;
-            // print!("{}:{}:[{}]:[{:?}] ", i, c, window, uniq);
+        if uniq.len() == winsize {
+            return Some(i);
         }
+        // print!("{}:[{:?}]:[{:?}] ", i, window, uniq);
     }
 
-    unique_pos
+    None
 }
 
 // Functional style
-fn find_unique_window_posv2(winsize: usize, comm: &str) -> usize {
-    let windows: Vec<usize> = comm
-        .chars()
-        .collect::<Vec<char>>()
-        .windows(winsize)
-        .map(|win| win.iter().unique().collect::<String>().len()) // only need len
-        .take_while(|l| *l != winsize)
-        .collect::<Vec<usize>>();
-
-    windows.len() + winsize
+fn find_unique_window_posv2(winsize: usize, comm: &[u8]) -> Option<usize> {
+    comm.windows(winsize)
+        .position(|win| win.iter().unique().count() == winsize) // only need len
+        .map(|start| start + winsize)
 }
 
-fn find_unique_window_posv3(winsize: usize, comm: &str) -> i32 {
-    let mut unique_pos: i32 = -1;
+fn find_unique_window_posv3(winsize: usize, comm: &[u8]) -> Option<usize> {
     let mut bits = BitSet::new();
-    let cbytes = comm.as_bytes();
 
-    for (i, _) in cbytes.iter().enumerate() {
-        if i > winsize - 1 {
-            let window = &cbytes[i - winsize..i];
+    for i in winsize..=comm.len() {
+        let window = &comm[i - winsize..i];
 
-            for c in window {
-                bits.insert((c - 97) as usize);
-            }
-
-            if bits.len() == winsize {
-                unique_pos = (i + 1) as i32;
-                break;
-            }
+        for c in window {
+            bits.insert(*c as usize);
+        }
 
-            //print!("{}:[{:?}]:[{:?}] ", i, window, cbytes);
+        if bits.len() == winsize {
+            return Some(i);
         }
+
+        //print!("{}:[{:?}]:[{:?}] ", i, window, comm);
         bits.clear();
     }
 
-    unique_pos
+    None
 }
 
-fn find_unique_window_posv4(winsize: usize, comm: &str) -> i32 {
-    let mut unique_pos: i32 = -1;
+// Only 32 bits of mask, so this one is limited to lowercase letters (and the six
+// bytes after 'z'). Anything else means no answer rather than an underflow.
+fn find_unique_window_posv4(winsize: usize, comm: &[u8]) -> Option<usize> {
     let mut bits: u32 = 0;
-    let cbytes = comm.as_bytes();
 
-    for (i, _) in cbytes.iter().enumerate() {
-        if i > winsize - 1 {
-            let window = &cbytes[i - winsize..i];
-            for c in window {
-                bits = bits | 1 << (c - 97) as usize;
+    for i in winsize..=comm.len() {
+        let window = &comm[i - winsize..i];
+        for c in window {
+            let bit = c.checked_sub(b'a').filter(|b| *b < 32)?;
+            bits |= 1 << bit;
+        }
+        if bits.count_ones() == winsize as u32 {
+            return Some(i);
+        }
+        bits = 0;
+    }
+
+    None
+}
+
+// Fast version: check each window back to front with a 256 bit mask. On a repeat at j
+// no window starting at or before j can be a marker, so jump straight past it. Most
+// windows are rejected after a couple of bytes and most bytes are never looked at.
+fn find_unique_window_pos_skip(winsize: usize, comm: &[u8]) -> Option<usize> {
+    let mut end = winsize;
+
+    'outer: while end <= comm.len() {
+        let mut mask = [0u64; 4];
+
+        for j in (end - winsize..end).rev() {
+            let c = comm[j] as usize;
+            let bit = 1u64 << (c & 63);
+
+            if mask[c >> 6] & bit != 0 {
+                end = j + 1 + winsize;
+                continue 'outer;
             }
-            if bits.count_ones() == winsize as u32 {
-                unique_pos = (i + 1) as i32;
-                break;
+            mask[c >> 6] |= bit;
+        }
+
+        return Some(end);
+    }
+
+    None
+}
+
+trait MarkerFinder {
+    fn name(&self) -> &'static str;
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize>;
+
+    // false for finders that only understand part of the byte alphabet
+    fn full_alphabet(&self) -> bool {
+        true
+    }
+}
+
+struct FindV1;
+struct FindV2;
+struct FindV3;
+struct FindV4;
+struct FindSkip;
+struct FindStream;
+
+impl MarkerFinder for FindV1 {
+    fn name(&self) -> &'static str {
+        "v1"
+    }
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize> {
+        find_unique_window_pos(winsize, comm)
+    }
+}
+
+impl MarkerFinder for FindV2 {
+    fn name(&self) -> &'static str {
+        "v2"
+    }
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize> {
+        find_unique_window_posv2(winsize, comm)
+    }
+}
+
+impl MarkerFinder for FindV3 {
+    fn name(&self) -> &'static str {
+        "v3"
+    }
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize> {
+        find_unique_window_posv3(winsize, comm)
+    }
+}
+
+impl MarkerFinder for FindV4 {
+    fn name(&self) -> &'static str {
+        "v4"
+    }
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize> {
+        find_unique_window_posv4(winsize, comm)
+    }
+
+    fn full_alphabet(&self) -> bool {
+        false
+    }
+}
+
+impl MarkerFinder for FindSkip {
+    fn name(&self) -> &'static str {
+        "skip"
+    }
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize> {
+        find_unique_window_pos_skip(winsize, comm)
+    }
+}
+
+impl MarkerFinder for FindStream {
+    fn name(&self) -> &'static str {
+        "stream"
+    }
+
+    fn find(&self, winsize: usize, comm: &[u8]) -> Option<usize> {
+        find_first_marker(winsize, comm).unwrap()
+    }
+}
+
+fn all_finders() -> Vec<Box<dyn MarkerFinder>> {
+    vec![
+        Box::new(FindV1),
+        Box::new(FindV2),
+        Box::new(FindV3),
+        Box::new(FindV4),
+        Box::new(FindSkip),
+        Box::new(FindStream),
+    ]
+}
+
+// Small xorshift so the generated streams are the same on every run
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Random stream over `alphabet` consecutive byte values starting at `base`. Small
+// alphabets give late or missing markers, large ones give early markers.
+fn gen_stream(rng: &mut XorShift, len: usize, base: u8, alphabet: u8) -> Vec<u8> {
+    (0..len)
+        .map(|_| base.wrapping_add((rng.next() % alphabet as u64) as u8))
+        .collect()
+}
+
+// Run every finder over `rounds` generated streams and window sizes and make sure they
+// all agree. Returns the first disagreement.
+fn cross_check(
+    finders: &[Box<dyn MarkerFinder>],
+    rounds: usize,
+    seed: u64,
+) -> Result<usize, String> {
+    let mut rng = XorShift(seed | 1);
+    let mut checked = 0;
+
+    for round in 0..rounds {
+        let len = (rng.next() % 300) as usize;
+        let winsize = 1 + (rng.next() % 16) as usize;
+        let lowercase = round % 2 == 0;
+        let (base, alphabet) = if lowercase {
+            (b'a', 1 + (rng.next() % 26) as u8)
+        } else {
+            ((rng.next() % 256) as u8, 1 + (rng.next() % 255) as u8)
+        };
+        let stream = gen_stream(&mut rng, len, base, alphabet);
+
+        let mut results = finders
+            .iter()
+            .filter(|f| lowercase || f.full_alphabet())
+            .map(|f| (f.name(), f.find(winsize, &stream)));
+
+        let (first_name, first) = match results.next() {
+            Some(r) => r,
+            None => continue,
+        };
+
+        for (name, res) in results {
+            if res != first {
+                return Err(format!(
+                    "window {} over {:?}: {} = {:?}, {} = {:?}",
+                    winsize, stream, first_name, first, name, res
+                ));
             }
         }
-        bits = 0;
+        checked += 1;
+    }
+
+    Ok(checked)
+}
+
+// Streaming version: reads bytes from any io::Read and keeps a rolling count of every
+// byte value in the current window, so each byte costs O(1) and memory is just the
+// window plus a 256 entry table. Yields the end position (1-based, like the puzzle
+// answer) of every window whose bytes are all distinct.
+struct MarkerScanner<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+    winsize: usize,
+    ring: Vec<u8>,
+    counts: [usize; 256],
+    distinct: usize,
+    pos: usize,
+}
+
+impl<R: Read> MarkerScanner<R> {
+    fn new(winsize: usize, reader: R) -> Self {
+        assert!(winsize > 0, "window size must be at least 1");
+
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            winsize,
+            ring: vec![0; winsize],
+            counts: [0; 256],
+            distinct: 0,
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Iterator for MarkerScanner<R> {
+    type Item = io::Result<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let b = match self.bytes.next()? {
+                Ok(b) => b,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let slot = self.pos % self.winsize;
+            if self.pos >= self.winsize {
+                let old = self.ring[slot] as usize;
+                self.counts[old] -= 1;
+                if self.counts[old] == 0 {
+                    self.distinct -= 1;
+                }
+            }
+
+            self.ring[slot] = b;
+            self.counts[b as usize] += 1;
+            if self.counts[b as usize] == 1 {
+                self.distinct += 1;
+            }
+            self.pos += 1;
+
+            if self.distinct == self.winsize {
+                return Some(Ok(self.pos));
+            }
+        }
+    }
+}
+
+// Just the first marker, stops reading as soon as it is found
+fn find_first_marker<R: Read>(winsize: usize, reader: R) -> io::Result<Option<usize>> {
+    MarkerScanner::new(winsize, reader).next().transpose()
+}
+
+// One decoded frame: a marker followed by everything up to the next marker
+#[derive(Debug, PartialEq)]
+struct Frame {
+    index: usize,
+    marker_start: usize,
+    marker_end: usize,
+    payload_start: usize,
+    payload_end: usize,
+}
+
+impl Frame {
+    fn to_json(&self, comm: &[u8]) -> serde_json::Value {
+        json!({
+            "index": self.index,
+            "marker_start": self.marker_start,
+            "marker_end": self.marker_end,
+            "marker": String::from_utf8_lossy(&comm[self.marker_start..self.marker_end]),
+            "payload_start": self.payload_start,
+            "payload_len": self.payload_end - self.payload_start,
+            "payload": String::from_utf8_lossy(&comm[self.payload_start..self.payload_end]),
+        })
+    }
+}
+
+// Split a stream into frames. After each marker the search starts over on the bytes
+// that follow it, so markers never overlap. Anything before the first marker is
+// preamble and is not part of a frame.
+fn decode_frames(winsize: usize, comm: &[u8]) -> Vec<Frame> {
+    // An empty window matches without consuming anything, the search would never end
+    if winsize == 0 {
+        return Vec::new();
+    }
+    let mut markers: Vec<usize> = Vec::new();
+    let mut offset = 0;
+
+    while let Some(end) = find_unique_window_pos_skip(winsize, &comm[offset..]) {
+        debug_assert!(end > 0, "a marker has to move the search forward");
+        offset += end;
+        markers.push(offset);
+    }
+
+    markers
+        .iter()
+        .enumerate()
+        .map(|(index, &marker_end)| Frame {
+            index,
+            marker_start: marker_end - winsize,
+            marker_end,
+            payload_start: marker_end,
+            payload_end: markers
+                .get(index + 1)
+                .map_or(comm.len(), |next| next - winsize),
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct FrameStats {
+    frames: usize,
+    preamble: usize,
+    min_distance: Option<usize>,
+    max_distance: Option<usize>,
+    mean_distance: Option<f64>,
+    alphabet: BTreeMap<u8, usize>,
+}
+
+// Distances are measured marker end to marker end, alphabet usage counts payload bytes
+fn frame_stats(frames: &[Frame], comm: &[u8]) -> FrameStats {
+    let distances: Vec<usize> = frames
+        .windows(2)
+        .map(|w| w[1].marker_end - w[0].marker_end)
+        .collect();
+
+    let mut alphabet = BTreeMap::new();
+    for f in frames {
+        for b in &comm[f.payload_start..f.payload_end] {
+            *alphabet.entry(*b).or_insert(0) += 1;
+        }
+    }
+
+    FrameStats {
+        frames: frames.len(),
+        preamble: frames.first().map_or(comm.len(), |f| f.marker_start),
+        min_distance: distances.iter().copied().min(),
+        max_distance: distances.iter().copied().max(),
+        mean_distance: if distances.is_empty() {
+            None
+        } else {
+            Some(distances.iter().sum::<usize>() as f64 / distances.len() as f64)
+        },
+        alphabet,
     }
-    unique_pos
 }
 
 #[test]
 fn test_bitvecs() {
     let astr: Vec<u8> = vec![10, 11, 12, 10];
-    let winsize: usize = 14;
     let mut bits = BitSet::new();
     eprintln!("astr = {:?}", astr);
 
@@ -117,63 +449,301 @@ fn test_bitvecs() {
     eprintln!("bits = {:?}, len = {:?}", bits, bits.len());
 }
 
+#[test]
+fn test_example_comms4() {
+    let comms: Vec<&str> = TESTCOMMS.split('\n').collect();
+    assert_eq!(Some(5), find_unique_window_posv4(4, comms[0].as_bytes()));
+    assert_eq!(Some(6), find_unique_window_posv4(4, comms[1].as_bytes()));
+    assert_eq!(Some(10), find_unique_window_posv4(4, comms[2].as_bytes()));
+    assert_eq!(Some(11), find_unique_window_posv4(4, comms[3].as_bytes()));
+
+    let comms2: Vec<&str> = TESTCOMMS2.split('\n').collect();
+    assert_eq!(Some(19), find_unique_window_posv4(14, comms2[0].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_posv4(14, comms2[1].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_posv4(14, comms2[2].as_bytes()));
+    assert_eq!(Some(29), find_unique_window_posv4(14, comms2[3].as_bytes()));
+    assert_eq!(Some(26), find_unique_window_posv4(14, comms2[4].as_bytes()));
+}
+
 #[test]
 fn test_example_comms3() {
     let comms: Vec<&str> = TESTCOMMS.split("\n").collect();
-    assert_eq!(5, find_unique_window_posv3(4, &comms[0]));
-    assert_eq!(6, find_unique_window_posv3(4, &comms[1]));
-    assert_eq!(10, find_unique_window_posv3(4, &comms[2]));
-    assert_eq!(11, find_unique_window_posv3(4, &comms[3]));
+    assert_eq!(Some(5), find_unique_window_posv3(4, comms[0].as_bytes()));
+    assert_eq!(Some(6), find_unique_window_posv3(4, comms[1].as_bytes()));
+    assert_eq!(Some(10), find_unique_window_posv3(4, comms[2].as_bytes()));
+    assert_eq!(Some(11), find_unique_window_posv3(4, comms[3].as_bytes()));
     //
     let comms2: Vec<&str> = TESTCOMMS2.split("\n").collect();
-    assert_eq!(19, find_unique_window_posv3(14, &comms2[0]));
-    assert_eq!(23, find_unique_window_posv3(14, &comms2[1]));
-    assert_eq!(23, find_unique_window_posv3(14, &comms2[2]));
-    assert_eq!(29, find_unique_window_posv3(14, &comms2[3]));
-    assert_eq!(26, find_unique_window_posv3(14, &comms2[4]));
+    assert_eq!(Some(19), find_unique_window_posv3(14, comms2[0].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_posv3(14, comms2[1].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_posv3(14, comms2[2].as_bytes()));
+    assert_eq!(Some(29), find_unique_window_posv3(14, comms2[3].as_bytes()));
+    assert_eq!(Some(26), find_unique_window_posv3(14, comms2[4].as_bytes()));
 }
 
 #[test]
 fn test_example_comms2() {
     let comms: Vec<&str> = TESTCOMMS.split("\n").collect();
-    // find_unique_window_posv2(4, &comms[0]);
-    assert_eq!(5, find_unique_window_posv2(4, &comms[0]));
-    assert_eq!(6, find_unique_window_posv2(4, &comms[1]));
-    assert_eq!(10, find_unique_window_posv2(4, &comms[2]));
-    assert_eq!(11, find_unique_window_posv2(4, &comms[3]));
+    // find_unique_window_posv2(4, comms[0]);
+    assert_eq!(Some(5), find_unique_window_posv2(4, comms[0].as_bytes()));
+    assert_eq!(Some(6), find_unique_window_posv2(4, comms[1].as_bytes()));
+    assert_eq!(Some(10), find_unique_window_posv2(4, comms[2].as_bytes()));
+    assert_eq!(Some(11), find_unique_window_posv2(4, comms[3].as_bytes()));
 
     let comms2: Vec<&str> = TESTCOMMS2.split("\n").collect();
-    assert_eq!(19, find_unique_window_posv2(14, &comms2[0]));
-    assert_eq!(23, find_unique_window_posv2(14, &comms2[1]));
-    assert_eq!(23, find_unique_window_posv2(14, &comms2[2]));
-    assert_eq!(29, find_unique_window_posv2(14, &comms2[3]));
-    assert_eq!(26, find_unique_window_posv2(14, &comms2[4]));
+    assert_eq!(Some(19), find_unique_window_posv2(14, comms2[0].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_posv2(14, comms2[1].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_posv2(14, comms2[2].as_bytes()));
+    assert_eq!(Some(29), find_unique_window_posv2(14, comms2[3].as_bytes()));
+    assert_eq!(Some(26), find_unique_window_posv2(14, comms2[4].as_bytes()));
 }
 
 #[test]
 fn test_example_comms() {
     let comms: Vec<&str> = TESTCOMMS.split("\n").collect();
-    assert_eq!(5, find_unique_window_pos(4, &comms[0]));
-    assert_eq!(6, find_unique_window_pos(4, &comms[1]));
-    assert_eq!(10, find_unique_window_pos(4, &comms[2]));
-    assert_eq!(11, find_unique_window_pos(4, &comms[3]));
+    assert_eq!(Some(5), find_unique_window_pos(4, comms[0].as_bytes()));
+    assert_eq!(Some(6), find_unique_window_pos(4, comms[1].as_bytes()));
+    assert_eq!(Some(10), find_unique_window_pos(4, comms[2].as_bytes()));
+    assert_eq!(Some(11), find_unique_window_pos(4, comms[3].as_bytes()));
 
     let comms2: Vec<&str> = TESTCOMMS2.split("\n").collect();
-    assert_eq!(19, find_unique_window_pos(14, &comms2[0]));
-    assert_eq!(23, find_unique_window_pos(14, &comms2[1]));
-    assert_eq!(23, find_unique_window_pos(14, &comms2[2]));
-    assert_eq!(29, find_unique_window_pos(14, &comms2[3]));
-    assert_eq!(26, find_unique_window_pos(14, &comms2[4]));
+    assert_eq!(Some(19), find_unique_window_pos(14, comms2[0].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_pos(14, comms2[1].as_bytes()));
+    assert_eq!(Some(23), find_unique_window_pos(14, comms2[2].as_bytes()));
+    assert_eq!(Some(29), find_unique_window_pos(14, comms2[3].as_bytes()));
+    assert_eq!(Some(26), find_unique_window_pos(14, comms2[4].as_bytes()));
 }
 
-/*pos with window 4 = 1910
-pos with window 14 = 3381
+#[cfg(test)]
+fn all_markers(winsize: usize, stream: &[u8]) -> Vec<usize> {
+    MarkerScanner::new(winsize, stream)
+        .collect::<io::Result<_>>()
+        .unwrap()
+}
+
+#[test]
+fn test_example_comms_skip() {
+    let comms: Vec<&str> = TESTCOMMS.split('\n').collect();
+    assert_eq!(Some(5), find_unique_window_pos_skip(4, comms[0].as_bytes()));
+    assert_eq!(Some(6), find_unique_window_pos_skip(4, comms[1].as_bytes()));
+    assert_eq!(
+        Some(10),
+        find_unique_window_pos_skip(4, comms[2].as_bytes())
+    );
+    assert_eq!(
+        Some(11),
+        find_unique_window_pos_skip(4, comms[3].as_bytes())
+    );
+
+    let comms2: Vec<&str> = TESTCOMMS2.split('\n').collect();
+    assert_eq!(
+        Some(19),
+        find_unique_window_pos_skip(14, comms2[0].as_bytes())
+    );
+    assert_eq!(
+        Some(23),
+        find_unique_window_pos_skip(14, comms2[1].as_bytes())
+    );
+    assert_eq!(
+        Some(23),
+        find_unique_window_pos_skip(14, comms2[2].as_bytes())
+    );
+    assert_eq!(
+        Some(29),
+        find_unique_window_pos_skip(14, comms2[3].as_bytes())
+    );
+    assert_eq!(
+        Some(26),
+        find_unique_window_pos_skip(14, comms2[4].as_bytes())
+    );
+}
+
+#[test]
+fn test_finders_no_marker() {
+    // v1 used to report the last position here, v2 used len + winsize
+    for f in all_finders() {
+        assert_eq!(None, f.find(4, b"abcabcabc"), "{}", f.name());
+        assert_eq!(None, f.find(4, b"abc"), "{}", f.name());
+        assert_eq!(Some(4), f.find(4, b"abcd"), "{}", f.name());
+    }
+}
+
+#[test]
+fn test_finders_agree() {
+    assert_eq!(cross_check(&all_finders(), 5000, 2022), Ok(5000));
+}
+
+#[test]
+fn test_marker_scanner() {
+    let comms: Vec<&str> = TESTCOMMS.split('\n').collect();
+    let firsts: Vec<Option<usize>> = comms
+        .iter()
+        .map(|c| find_first_marker(4, c.as_bytes()).unwrap())
+        .collect();
+    assert_eq!(firsts, vec![Some(5), Some(6), Some(10), Some(11)]);
+
+    let comms2: Vec<&str> = TESTCOMMS2.split('\n').collect();
+    let firsts: Vec<Option<usize>> = comms2
+        .iter()
+        .map(|c| find_first_marker(14, c.as_bytes()).unwrap())
+        .collect();
+    assert_eq!(
+        firsts,
+        vec![Some(19), Some(23), Some(23), Some(29), Some(26)]
+    );
+
+    // Bytes below 'a' are ordinary symbols here, not an underflow
+    assert_eq!(all_markers(4, b"AAB1\nAB"), vec![5, 6, 7]);
+    assert_eq!(all_markers(1, b"aa"), vec![1, 2]);
+    assert_eq!(find_first_marker(3, "aabb".as_bytes()).unwrap(), None);
+}
+
+#[test]
+fn test_marker_scanner_all_positions() {
+    // Compare against a brute force check of every window, for several window sizes
+    let stream: Vec<u8> = (0..5000u32)
+        .map(|i| (i * 7919 % 23 + i % 5) as u8)
+        .collect();
+
+    for winsize in [1, 2, 4, 14, 24] {
+        let want: Vec<usize> = stream
+            .windows(winsize)
+            .enumerate()
+            .filter(|(_, w)| w.iter().unique().count() == winsize)
+            .map(|(i, _)| i + winsize)
+            .collect();
+
+        assert_eq!(all_markers(winsize, &stream), want);
+    }
+}
+
+#[test]
+fn test_decode_frames() {
+    let comm = b"aaabcdxxxxdcbaqqefgh";
+    let frames = decode_frames(4, comm);
+
+    assert_eq!(
+        frames,
+        vec![
+            Frame {
+                index: 0,
+                marker_start: 2,
+                marker_end: 6,
+                payload_start: 6,
+                payload_end: 9,
+            },
+            Frame {
+                index: 1,
+                marker_start: 9,
+                marker_end: 13,
+                payload_start: 13,
+                payload_end: 15,
+            },
+            Frame {
+                index: 2,
+                marker_start: 15,
+                marker_end: 19,
+                payload_start: 19,
+                payload_end: 20,
+            },
+        ]
+    );
+
+    assert_eq!(frames[0].to_json(comm)["marker"], "abcd");
+    assert_eq!(frames[1].to_json(comm)["payload"], "aq");
+    assert_eq!(frames[2].to_json(comm)["marker"], "qefg");
+    assert_eq!(frames[2].to_json(comm)["payload_len"], 1);
+
+    let stats = frame_stats(&frames, comm);
+    assert_eq!(stats.frames, 3);
+    assert_eq!(stats.preamble, 2);
+    assert_eq!(stats.min_distance, Some(6));
+    assert_eq!(stats.max_distance, Some(7));
+    assert_eq!(stats.mean_distance, Some(6.5));
+    assert_eq!(
+        stats.alphabet,
+        BTreeMap::from([(b'a', 1), (b'h', 1), (b'q', 1), (b'x', 3)])
+    );
+
+    assert_eq!(decode_frames(4, b"aaaa"), vec![]);
+    assert_eq!(decode_frames(0, b"abcd"), vec![]);
+    assert_eq!(frame_stats(&[], b"aaaa").preamble, 4);
+}
+
+/*pos with window 4 = 1909
+pos with window 14 = 3380
 */
 fn main() -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string("src/puzzle.txt")?;
-    let pos = find_unique_window_posv4(4, contents.as_str());
-    eprintln!("pos with window 4 = {:?}", pos);
-    let pos = find_unique_window_posv4(14, contents.as_str());
-    eprintln!("pos with window 14 = {:?}", pos);
+    let mut args = std::env::args().skip(1).peekable();
+
+    // day6 check [rounds] [seed]
+    if args.peek().map(|a| a.as_str()) == Some("check") {
+        let rounds = args.nth(1).map_or(Ok(100_000), |a| a.parse())?;
+        let seed = args.next().map_or(Ok(2022), |a| a.parse())?;
+        let checked = cross_check(&all_finders(), rounds, seed)?;
+        eprintln!("{} streams checked, all finders agree", checked);
+        return Ok(());
+    }
+
+    // day6 frames <winsize> [path], frames as JSONL on stdout and stats on stderr
+    if args.peek().map(|a| a.as_str()) == Some("frames") {
+        let winsize: usize = args
+            .nth(1)
+            .ok_or("usage: day6 frames <winsize> [path]")?
+            .parse()?;
+        if winsize == 0 {
+            return Err("usage: day6 frames <winsize> [path], winsize is at least 1".into());
+        }
+        let path = args.next().unwrap_or_else(|| "src/puzzle.txt".to_string());
+        let contents = fs::read(path)?;
+        let comm = contents.strip_suffix(b"\n").unwrap_or(&contents);
+
+        let frames = decode_frames(winsize, comm);
+        for f in &frames {
+            println!("{}", f.to_json(comm));
+        }
+
+        let stats = frame_stats(&frames, comm);
+        eprintln!("frames = {}, preamble = {}", stats.frames, stats.preamble);
+        eprintln!(
+            "distance min = {:?}, max = {:?}, mean = {:?}",
+            stats.min_distance, stats.max_distance, stats.mean_distance
+        );
+        let usage: Vec<String> = stats
+            .alphabet
+            .iter()
+            .map(|(b, n)| format!("{}:{}", *b as char, n))
+            .collect();
+        eprintln!("alphabet ({} symbols) = {}", usage.len(), usage.join(" "));
+        return Ok(());
+    }
+
+    let path = args.next().unwrap_or_else(|| "src/puzzle.txt".to_string());
+
+    // Extra window sizes list every marker instead of just the first, e.g. day6 big.txt 4 14
+    let winsizes: Vec<NonZeroUsize> = args
+        .map(|a| {
+            a.parse()
+                .map_err(|_| format!("bad window size {:?}, want 1 or more", a))
+        })
+        .collect::<Result<_, _>>()?;
+
+    if winsizes.is_empty() {
+        let pos = find_first_marker(4, File::open(&path)?)?;
+        eprintln!("pos with window 4 = {:?}", pos);
+        let pos = find_first_marker(14, File::open(&path)?)?;
+        eprintln!("pos with window 14 = {:?}", pos);
+        return Ok(());
+    }
+
+    for winsize in winsizes {
+        let mut count = 0;
+        for pos in MarkerScanner::new(winsize.get(), File::open(&path)?) {
+            println!("{}\t{}", winsize, pos?);
+            count += 1;
+        }
+        eprintln!("{} markers with window {}", count, winsize);
+    }
+
     Ok(())
 }
@@ -1,11 +1,40 @@
-use std::cell::RefCell;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::error::Error;
-use std::fmt;
 use std::fs;
-use std::rc::{Rc, Weak};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-type NodeRef = Rc<RefCell<Node>>;
-type ParentRef = Weak<RefCell<Node>>;
+#[cfg(test)]
+const TEST: &str = "$ cd /
+$ ls
+dir a
+14848514 b.txt
+8504156 c.dat
+dir d
+$ cd a
+$ ls
+dir e
+29116 f
+2557 g
+62596 h.lst
+$ cd e
+$ ls
+584 i
+$ cd ..
+$ cd ..
+$ cd d
+$ ls
+4060174 j
+8033020 d.log
+5626152 d.ext
+7214296 k";
+
+// Directories live in one Vec and point at each other by index, the root is always 0
+type NodeId = usize;
+
+const ROOT: NodeId = 0;
 
 #[derive(Debug)]
 struct File {
@@ -16,137 +45,1341 @@ struct File {
 #[derive(Debug)]
 struct Node {
     name: String,
-    children: Vec<NodeRef>,
-    parent: Option<ParentRef>,
+    children: Vec<NodeId>,
+    parent: Option<NodeId>,
     files: Vec<File>,
 }
 
 impl Node {
-    fn new(name: &str) -> Node {
-        return Node {
+    fn new(name: &str, parent: Option<NodeId>) -> Node {
+        Node {
             name: name.to_string(),
-            parent: None,
+            parent,
             children: Vec::new(),
             files: Vec::new(),
-        };
+        }
     }
+}
+
+// What a name inside a directory refers to, a subdirectory or an index into `files`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Entry {
+    Dir(NodeId),
+    File(usize),
+}
+
+#[derive(Debug)]
+struct Tree {
+    nodes: Vec<Node>,
+    // (parent, name) -> entry, so cd and ls are not a scan over every sibling
+    lookup: HashMap<(NodeId, String), Entry>,
+    // Total size of every directory, filled in once by compute_sizes
+    sizes: Vec<usize>,
+}
 
-    fn new_noderef(node: Node) -> NodeRef {
-        return Rc::new(RefCell::new(node));
+impl Tree {
+    fn new() -> Tree {
+        Tree {
+            nodes: vec![Node::new("/", None)],
+            lookup: HashMap::new(),
+            sizes: Vec::new(),
+        }
     }
 
-    fn add_file(&mut self, name: &str, size: usize) {
-        self.files.push(File {
-            name: name.to_string(),
-            size: size,
-        });
+    // Names become path components in materialize, so nothing that could step out of
+    // the directory they are listed in
+    fn check_name(&self, id: NodeId, name: &str) -> Result<(), String> {
+        match name {
+            "" | "." | ".." => Err(format!("{}{:?} is not a valid name", self.path(id), name)),
+            _ if name.contains('/') => {
+                Err(format!("{}{:?} is not a valid name", self.path(id), name))
+            }
+            _ => Ok(()),
+        }
     }
 
-    fn get_parent(&self) -> Option<Rc<RefCell<Node>>> {
-        self.parent.as_ref().map_or(None, |p| p.upgrade())
+    // Listing the same file again is fine, as long as it has not changed size
+    fn add_file(&mut self, id: NodeId, name: &str, size: usize) -> Result<(), String> {
+        self.check_name(id, name)?;
+        match self.lookup.get(&(id, name.to_string())) {
+            Some(Entry::File(idx)) => {
+                let file = &self.nodes[id].files[*idx];
+                if file.size != size {
+                    return Err(format!(
+                        "{}{} changed size from {} to {}",
+                        self.path(id),
+                        file.name,
+                        file.size,
+                        size
+                    ));
+                }
+                Ok(())
+            }
+            Some(Entry::Dir(_)) => Err(format!(
+                "{}{} listed as both a directory and a file",
+                self.path(id),
+                name
+            )),
+            None => {
+                let idx = self.nodes[id].files.len();
+                self.nodes[id].files.push(File {
+                    name: name.to_string(),
+                    size,
+                });
+                self.lookup.insert((id, name.to_string()), Entry::File(idx));
+                Ok(())
+            }
+        }
     }
 
-    fn add_child(parent: &mut NodeRef, child: NodeRef) {
-        child.borrow_mut().parent = Some(Rc::downgrade(&parent.clone()));
-        parent.borrow_mut().children.push(child);
+    fn get_parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id].parent
     }
 
-    fn get_child(&self, name: &str) -> Option<NodeRef> {
-        self.children
-            .iter()
-            .filter(|c| c.borrow().name == name)
-            .map(|c| c.clone())
-            .last()
-    }
-
-    fn init_tree(root: NodeRef, contents: &str) -> NodeRef {
-        let lines: Vec<&str> = contents.split("\n").collect();
-        let root = Node::new_noderef(Node::new("/"));
-        let mut cur: NodeRef = root.clone();
-        for line in &lines[1..] {
-            match line.split(" ").collect::<Vec<&str>>()[..] {
-                ["$", "ls"] => {}
-                ["$", "cd", ".."] => {
-                    cur = cur.clone().borrow().get_parent().unwrap();
-                }
-                ["$", "cd", directory] => {
-                    cur = cur.clone().borrow().get_child(directory).unwrap();
-                }
-                ["dir", name] => {
-                    Node::add_child(&mut cur, Node::new_noderef(Node::new(name)));
-                }
-                [size, name] => {
-                    cur.borrow_mut().add_file(name, size.parse().unwrap());
+    // Returns the existing directory if it was already seen
+    fn add_child(&mut self, parent: NodeId, name: &str) -> Result<NodeId, String> {
+        self.check_name(parent, name)?;
+        match self.lookup.get(&(parent, name.to_string())) {
+            Some(Entry::Dir(child)) => Ok(*child),
+            Some(Entry::File(_)) => Err(format!(
+                "{}{} listed as both a file and a directory",
+                self.path(parent),
+                name
+            )),
+            None => {
+                let child = self.nodes.len();
+                self.nodes.push(Node::new(name, Some(parent)));
+                self.nodes[parent].children.push(child);
+                self.lookup
+                    .insert((parent, name.to_string()), Entry::Dir(child));
+                Ok(child)
+            }
+        }
+    }
+
+    fn get_child(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        match self.lookup.get(&(id, name.to_string())) {
+            Some(Entry::Dir(child)) => Some(*child),
+            _ => None,
+        }
+    }
+
+    // Absolute path of a directory, always ending in '/'
+    fn path(&self, id: NodeId) -> String {
+        let mut names = Vec::new();
+        let mut cur = id;
+        while let Some(parent) = self.get_parent(cur) {
+            names.push(self.nodes[cur].name.as_str());
+            cur = parent;
+        }
+        names.reverse();
+
+        let mut path = String::from("/");
+        for name in names {
+            path.push_str(name);
+            path.push('/');
+        }
+        path
+    }
+
+    // Follow a cd argument from `cur`: absolute or relative, any number of segments.
+    // A directory we have not seen listed yet still exists if we can cd into it.
+    fn resolve(&mut self, cur: NodeId, path: &str) -> Result<NodeId, String> {
+        let mut id = if path.starts_with('/') { ROOT } else { cur };
+
+        for seg in path.split('/').filter(|s| !s.is_empty()) {
+            id = match seg {
+                "." => id,
+                ".." => self.get_parent(id).unwrap_or(ROOT),
+                name => self.add_child(id, name)?,
+            };
+        }
+
+        Ok(id)
+    }
+
+    fn init_tree(contents: &str) -> Result<Tree, String> {
+        let mut tree = Tree::new();
+        let mut cur = ROOT;
+        // Directory whose ls output we are reading, if any
+        let mut listing: Option<NodeId> = None;
+
+        for (n, line) in contents.lines().enumerate() {
+            let err = |e: String| format!("line {}: {}", n + 1, e);
+
+            match line.split(' ').collect::<Vec<&str>>()[..] {
+                [""] => {}
+                ["$", "ls"] => listing = Some(cur),
+                ["$", "cd", path] => {
+                    listing = None;
+                    cur = tree.resolve(cur, path).map_err(err)?;
                 }
-                _ => {}
+                ["$", ..] => return Err(err(format!("unknown command {:?}", line))),
+                ["dir", name] => match listing {
+                    Some(dir) => {
+                        tree.add_child(dir, name).map_err(err)?;
+                    }
+                    None => return Err(err(format!("output outside of ls {:?}", line))),
+                },
+                [size, name] => match (listing, size.parse()) {
+                    (Some(dir), Ok(size)) => tree.add_file(dir, name, size).map_err(err)?,
+                    (None, _) => return Err(err(format!("output outside of ls {:?}", line))),
+                    (_, Err(_)) => return Err(err(format!("bad file size {:?}", size))),
+                },
+                _ => return Err(err(format!("can't parse {:?}", line))),
             }
         }
-        root.clone()
+
+        tree.compute_sizes();
+        Ok(tree)
     }
 
-    fn dirsize(&self, size: usize) -> usize {
-        self.children
+    // Children are always created after their parent, so walking the arena backwards
+    // sees every directory after all of its subdirectories. One pass, no recursion.
+    fn compute_sizes(&mut self) {
+        let mut sizes: Vec<usize> = self
+            .nodes
             .iter()
-            .map(|c| c.borrow().dirsize(size))
-            .sum::<usize>()
-            + self.files.iter().map(|f| f.size).sum::<usize>()
+            .map(|n| n.files.iter().map(|f| f.size).sum())
+            .collect();
+
+        for id in (1..self.nodes.len()).rev() {
+            if let Some(parent) = self.nodes[id].parent {
+                sizes[parent] += sizes[id];
+            }
+        }
+
+        self.sizes = sizes;
+    }
+
+    fn dirsize(&self, id: NodeId) -> usize {
+        self.sizes[id]
     }
 
     // Sum all directories under `limit`
     fn sum_under_limit(&self, limit: usize) -> usize {
-        let mut size = self
-            .children
-            .iter()
-            .map(|c| c.borrow().sum_under_limit(limit))
-            .sum();
+        self.sizes.iter().filter(|&&s| s <= limit).sum()
+    }
+
+    // Smallest directory whose deletion leaves `wanted` bytes free on a disk of
+    // `disk_size`. Ok(None) when there is already enough room.
+    fn dir_to_free(&self, disk_size: usize, wanted: usize) -> Result<Option<NodeId>, String> {
+        if wanted > disk_size {
+            return Err(format!(
+                "can't free {} bytes on a {} byte disk",
+                wanted, disk_size
+            ));
+        }
 
-        if self.dirsize(0) <= limit {
-            size += self.dirsize(0);
+        let free = disk_size.saturating_sub(self.dirsize(ROOT));
+        if free >= wanted {
+            return Ok(None);
         }
 
-        size
+        let need_space = wanted - free;
+        (0..self.nodes.len())
+            .filter(|&id| self.dirsize(id) >= need_space)
+            .min_by_key(|&id| self.dirsize(id))
+            .map(Some)
+            .ok_or(format!(
+                "can't free {} bytes on a {} byte disk",
+                wanted, disk_size
+            ))
     }
 
-    // Find dir with largest size < limit
-    fn largest_under_limit(&self, limit: usize) -> usize {
-        let mut size = self
+    // Depth of every directory, / is 0. Parents come before children in the arena.
+    fn depths(&self) -> Vec<usize> {
+        let mut depths = vec![0; self.nodes.len()];
+        for id in 1..self.nodes.len() {
+            if let Some(parent) = self.nodes[id].parent {
+                depths[id] = depths[parent] + 1;
+            }
+        }
+        depths
+    }
+
+    // Read-only path lookup, relative paths start at `cur`. Never creates anything.
+    fn find_dir(&self, cur: NodeId, path: &str) -> Option<NodeId> {
+        let mut id = if path.starts_with('/') { ROOT } else { cur };
+
+        for seg in path.split('/').filter(|s| !s.is_empty()) {
+            id = match seg {
+                "." => id,
+                ".." => self.get_parent(id).unwrap_or(ROOT),
+                name => self.get_child(id, name)?,
+            };
+        }
+
+        Some(id)
+    }
+
+    // Size of whatever `path` names, a directory's total or a single file
+    fn path_size(&self, cur: NodeId, path: &str) -> Option<usize> {
+        if let Some(id) = self.find_dir(cur, path) {
+            return Some(self.dirsize(id));
+        }
+
+        let (dir, name) = match path.trim_end_matches('/').rsplit_once('/') {
+            Some(("", name)) => (self.find_dir(ROOT, "/")?, name),
+            Some((dir, name)) => (self.find_dir(cur, dir)?, name),
+            None => (cur, path),
+        };
+
+        match self.lookup.get(&(dir, name.to_string()))? {
+            Entry::File(idx) => Some(self.nodes[dir].files[*idx].size),
+            Entry::Dir(id) => Some(self.dirsize(*id)),
+        }
+    }
+
+    // Entries of one directory, subdirectories and files together, biggest first
+    fn sorted_entries(&self, id: NodeId) -> Vec<(usize, &str, Option<NodeId>)> {
+        let node = &self.nodes[id];
+        let mut entries: Vec<(usize, &str, Option<NodeId>)> = node
             .children
             .iter()
-            .map(|c| c.borrow().largest_under_limit(limit))
-            .filter(|s| s >= &limit)
-            .min()
-            .unwrap_or(usize::MAX);
+            .map(|&c| (self.dirsize(c), self.nodes[c].name.as_str(), Some(c)))
+            .chain(node.files.iter().map(|f| (f.size, f.name.as_str(), None)))
+            .collect();
+        entries.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        entries
+    }
 
-        let dirsize = self.dirsize(0);
+    // Like `tree` with cumulative sizes, biggest entries first. Directories deeper than
+    // `max_depth` below `id` are shown with their size but not expanded.
+    fn render_tree(&self, id: NodeId, max_depth: Option<usize>) -> String {
+        let mut out = format!("{} {}\n", self.dirsize(id), self.path(id));
+        // (prefix, last in its directory, entry, depth), popped in display order
+        let mut stack = Vec::new();
 
-        if dirsize >= limit && dirsize < size {
-            size = dirsize
+        let push_entries = |stack: &mut Vec<_>, dir: NodeId, prefix: String, depth: usize| {
+            let entries = self.sorted_entries(dir);
+            let count = entries.len();
+            for (i, entry) in entries.into_iter().enumerate().rev() {
+                stack.push((prefix.clone(), i + 1 == count, entry, depth));
+            }
+        };
+        if max_depth.is_none_or(|max| max > 0) {
+            push_entries(&mut stack, id, String::new(), 1);
+        }
+
+        while let Some((prefix, last, (size, name, child), depth)) = stack.pop() {
+            let branch = if last { "└── " } else { "├── " };
+            let slash = if child.is_some() { "/" } else { "" };
+            out.push_str(&format!("{}{}{} {}{}\n", prefix, branch, size, name, slash));
+
+            if let Some(child) = child {
+                if max_depth.is_none_or(|max| depth < max) {
+                    let more = if last { "    " } else { "│   " };
+                    push_entries(&mut stack, child, format!("{}{}", prefix, more), depth + 1);
+                }
+            }
+        }
+
+        out
+    }
+
+    // `du -d N`: every directory at most `max_depth` below `id`, subdirectories before
+    // their parent
+    fn du(&self, id: NodeId, max_depth: Option<usize>) -> Vec<(usize, String)> {
+        let mut out = Vec::new();
+        // (directory, depth, children already emitted)
+        let mut stack = vec![(id, 0, false)];
+
+        while let Some((dir, depth, done)) = stack.pop() {
+            if done {
+                out.push((self.dirsize(dir), self.path(dir)));
+                continue;
+            }
+
+            stack.push((dir, depth, true));
+            if max_depth.is_none_or(|max| depth < max) {
+                for &c in self.nodes[dir].children.iter().rev() {
+                    stack.push((c, depth + 1, false));
+                }
+            }
+        }
+
+        out
+    }
+
+    // Every directory matching the filter, in arena order (parents before children)
+    fn find(&self, filter: &DirFilter) -> Vec<NodeId> {
+        let depths = self.depths();
+
+        (0..self.nodes.len())
+            .filter(|&id| filter.matches(&self.nodes[id].name, self.dirsize(id), depths[id]))
+            .collect()
+    }
+
+    // How many directory levels there are below `id`, 0 if it has no subdirectories
+    fn levels_below(&self, id: NodeId) -> usize {
+        let depths = self.depths();
+        let mut inside = vec![false; self.nodes.len()];
+        inside[id] = true;
+        let mut levels = 0;
+        for dir in id + 1..self.nodes.len() {
+            inside[dir] = self.get_parent(dir).is_some_and(|p| inside[p]);
+            if inside[dir] {
+                levels = levels.max(depths[dir] - depths[id]);
+            }
+        }
+        levels
+    }
+
+    // serde_json won't read anything nested deeper than JSON_NESTING, so refuse to
+    // write it. `nesting` is how deep a format nests the deepest file.
+    fn check_nesting(&self, nesting: usize) -> Result<(), String> {
+        if nesting > JSON_NESTING {
+            return Err(format!(
+                "too deep for JSON, it would nest {} levels and the limit is {}",
+                nesting, JSON_NESTING
+            ));
+        }
+        Ok(())
+    }
+
+    // Nested JSON, every directory with its total size, subdirectories then files
+    fn to_json(&self, id: NodeId) -> Result<Value, String> {
+        // Each directory is an object holding a children array
+        self.check_nesting(2 * self.levels_below(id) + 3)?;
+
+        // Descendants of `id` all come after it in the arena, parents before children
+        let mut inside = vec![false; self.nodes.len()];
+        inside[id] = true;
+        for dir in id + 1..self.nodes.len() {
+            inside[dir] = self.get_parent(dir).is_some_and(|p| inside[p]);
+        }
+
+        // Bottom up, every directory's children are built before it
+        let mut built: HashMap<NodeId, Value> = HashMap::new();
+
+        for dir in (id..self.nodes.len()).rev() {
+            if !inside[dir] {
+                continue;
+            }
+
+            let node = &self.nodes[dir];
+            let children: Vec<Value> = node
+                .children
+                .iter()
+                .map(|c| built.remove(c).unwrap())
+                .chain(
+                    node.files
+                        .iter()
+                        .map(|f| json!({"name": f.name, "type": "file", "size": f.size})),
+                )
+                .collect();
+
+            built.insert(
+                dir,
+                json!({
+                    "name": node.name,
+                    "type": "dir",
+                    "size": self.dirsize(dir),
+                    "children": children,
+                }),
+            );
         }
 
-        size
+        Ok(built.remove(&id).unwrap())
     }
+
+    // ncdu's export format: a directory is an array of its info object followed by its
+    // entries, a file is just its info object. See ncdu(1), "-o FILE".
+    fn to_ncdu(&self, timestamp: u64) -> Result<Value, String> {
+        // The export array, then one array per directory
+        self.check_nesting(self.levels_below(ROOT) + 3)?;
+
+        let mut built: HashMap<NodeId, Value> = HashMap::new();
+
+        for dir in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[dir];
+            let mut entries = vec![json!({ "name": node.name })];
+            entries.extend(node.children.iter().map(|c| built.remove(c).unwrap()));
+            entries.extend(
+                node.files
+                    .iter()
+                    .map(|f| json!({"name": f.name, "asize": f.size, "dsize": f.size})),
+            );
+            built.insert(dir, Value::Array(entries));
+        }
+
+        Ok(json!([
+            1,
+            2,
+            {"progname": "day7", "progver": env!("CARGO_PKG_VERSION"), "timestamp": timestamp},
+            built.remove(&ROOT).unwrap(),
+        ]))
+    }
+
+    // Load either our nested JSON or an ncdu export back into a tree
+    fn from_json(value: &Value) -> Result<Tree, String> {
+        let mut tree = Tree::new();
+        let root = match value {
+            Value::Array(top) => top.get(3).ok_or("ncdu export without a root directory")?,
+            _ => value,
+        };
+
+        let mut stack = vec![(ROOT, root)];
+        while let Some((dir, value)) = stack.pop() {
+            let entries: Vec<&Value> = match value {
+                // ncdu: [info, entries...]
+                Value::Array(items) => items.iter().skip(1).collect(),
+                // ours: {"children": [...]}
+                _ => match value.get("children") {
+                    Some(Value::Array(items)) => items.iter().collect(),
+                    _ => Vec::new(),
+                },
+            };
+
+            for entry in entries {
+                let info = match entry {
+                    Value::Array(items) => items.first().unwrap_or(&Value::Null),
+                    _ => entry,
+                };
+                let name = info
+                    .get("name")
+                    .and_then(|n| n.as_str())
+                    .ok_or(format!("entry without a name in {}", tree.path(dir)))?;
+                if name.is_empty() || name.contains(' ') || name.contains('/') {
+                    return Err(format!(
+                        "{}{:?} can't appear in a transcript",
+                        tree.path(dir),
+                        name
+                    ));
+                }
+
+                let is_dir =
+                    entry.is_array() || info.get("type").and_then(|t| t.as_str()) == Some("dir");
+                if is_dir {
+                    let child = tree.add_child(dir, name)?;
+                    stack.push((child, entry));
+                } else {
+                    let size = info
+                        .get("size")
+                        .or_else(|| info.get("asize"))
+                        .and_then(|s| s.as_u64())
+                        .ok_or(format!("{}{} has no size", tree.path(dir), name))?;
+                    tree.add_file(dir, name, size as usize)?;
+                }
+            }
+        }
+
+        tree.compute_sizes();
+        Ok(tree)
+    }
+
+    // A `$ cd`/`$ ls` session that init_tree turns back into this tree
+    fn to_transcript(&self) -> String {
+        let mut out = String::from("$ cd /\n");
+        // None means "cd .." once a directory's subtree is done
+        let mut stack = vec![Some(ROOT)];
+
+        while let Some(entry) = stack.pop() {
+            let dir = match entry {
+                Some(dir) => dir,
+                None => {
+                    out.push_str("$ cd ..\n");
+                    continue;
+                }
+            };
+
+            let node = &self.nodes[dir];
+            if dir != ROOT {
+                out.push_str(&format!("$ cd {}\n", node.name));
+                stack.push(None);
+            }
+
+            out.push_str("$ ls\n");
+            for &c in &node.children {
+                out.push_str(&format!("dir {}\n", self.nodes[c].name));
+            }
+            for f in &node.files {
+                out.push_str(&format!("{} {}\n", f.size, f.name));
+            }
+
+            stack.extend(node.children.iter().rev().map(|&c| Some(c)));
+        }
+
+        out
+    }
+
+    // Recreate the tree under `target` as real directories and sparse files of the
+    // recorded sizes, so tools can be pointed at it without using the disk space.
+    // `target` must not exist yet or be empty.
+    fn materialize(&self, target: &Path) -> io::Result<()> {
+        if target.exists() && fs::read_dir(target)?.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is not empty", target.display()),
+            ));
+        }
+
+        // Parents come before children in the arena so create_dir never misses a parent
+        let mut paths: Vec<PathBuf> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let path = match node.parent {
+                Some(parent) => paths[parent].join(&node.name),
+                None => target.to_path_buf(),
+            };
+            fs::create_dir_all(&path)?;
+
+            for f in &node.files {
+                // set_len on a new file leaves a hole instead of writing zeroes
+                fs::File::create(path.join(&f.name))?.set_len(f.size as u64)?;
+            }
+            paths.push(path);
+        }
+
+        Ok(())
+    }
+
+    // The other direction: walk a real directory and record it as a tree, using the
+    // apparent file sizes. Symlinks and other special files are skipped.
+    fn scan_dir(root: &Path) -> io::Result<Tree> {
+        let mut tree = Tree::new();
+        let mut stack = vec![(ROOT, root.to_path_buf())];
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        while let Some((dir, path)) = stack.pop() {
+            let mut entries = fs::read_dir(&path)?.collect::<io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|e| e.file_name());
+
+            for entry in entries {
+                let name = entry
+                    .file_name()
+                    .into_string()
+                    .map_err(|n| invalid(format!("{:?} is not UTF-8", n)))?;
+                if name.contains(' ') {
+                    return Err(invalid(format!(
+                        "{:?} in {} can't appear in a transcript",
+                        name,
+                        path.display()
+                    )));
+                }
+
+                let kind = entry.file_type()?;
+                if kind.is_dir() {
+                    let child = tree.add_child(dir, &name).map_err(invalid)?;
+                    stack.push((child, entry.path()));
+                } else if kind.is_file() {
+                    let size = entry.metadata()?.len() as usize;
+                    tree.add_file(dir, &name, size).map_err(invalid)?;
+                }
+            }
+        }
+
+        tree.compute_sizes();
+        Ok(tree)
+    }
+}
+
+// Predicate for Tree::find, unset fields match everything
+#[derive(Debug, Default)]
+struct DirFilter {
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+    min_depth: Option<usize>,
+    max_depth: Option<usize>,
+    name: Option<String>,
+}
+
+impl DirFilter {
+    fn matches(&self, name: &str, size: usize, depth: usize) -> bool {
+        self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self.min_depth.is_none_or(|min| depth >= min)
+            && self.max_depth.is_none_or(|max| depth <= max)
+            && self.name.as_ref().is_none_or(|pat| glob_match(pat, name))
+    }
+}
+
+// Shell style glob with `*` and `?`
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    // Where the last `*` was and how much of the name it has swallowed so far
+    let mut star: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((spi, sni)) = star {
+            pi = spi + 1;
+            ni = sni + 1;
+            star = Some((spi, sni + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+// Options of a report command, like `-d 2` for tree and du. Every flag in `flags` takes
+// a value and any other argument starting with '-' is an error. Positional arguments
+// come back in order.
+fn take_flags<'a>(
+    args: &[&'a str],
+    flags: &[&str],
+) -> Result<(HashMap<String, &'a str>, Vec<&'a str>), String> {
+    let mut found = HashMap::new();
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(&arg) = iter.next() {
+        if flags.contains(&arg) {
+            let value = iter.next().ok_or(format!("{} needs a value", arg))?;
+            found.insert(arg.to_string(), *value);
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option {}", arg));
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    Ok((found, rest))
+}
+
+fn flag_num(flags: &HashMap<String, &str>, flag: &str) -> Result<Option<usize>, String> {
+    flags
+        .get(flag)
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("{} expects a number, got {:?}", flag, v))
+        })
+        .transpose()
+}
+
+// Reporting commands over a parsed tree, relative paths start at `cwd`
+//   tree [-d N] [PATH]
+//   du [-d N] [PATH]
+//   size PATH
+//   find [--min N] [--max N] [--mindepth N] [--maxdepth N] [--name GLOB]
+//   json | ncdu | transcript
+fn run_report(tree: &Tree, cwd: NodeId, args: &[&str]) -> Result<String, String> {
+    let (cmd, args) = args.split_first().ok_or("no command")?;
+    let dir_arg = |rest: &[&str]| match rest {
+        [] => Ok(cwd),
+        [path] => tree
+            .find_dir(cwd, path)
+            .ok_or(format!("no such directory {}", path)),
+        _ => Err("too many arguments".to_string()),
+    };
+
+    match *cmd {
+        "tree" => {
+            let (flags, rest) = take_flags(args, &["-d"])?;
+            Ok(tree.render_tree(dir_arg(&rest)?, flag_num(&flags, "-d")?))
+        }
+        "du" => {
+            let (flags, rest) = take_flags(args, &["-d"])?;
+            let rows = tree.du(dir_arg(&rest)?, flag_num(&flags, "-d")?);
+            Ok(rows
+                .iter()
+                .map(|(size, path)| format!("{}\t{}\n", size, path))
+                .collect())
+        }
+        "size" => match args {
+            [path] => tree
+                .path_size(cwd, path)
+                .map(|size| format!("{}\n", size))
+                .ok_or(format!("no such file or directory {}", path)),
+            _ => Err("usage: size PATH".to_string()),
+        },
+        "find" => {
+            let (flags, rest) = take_flags(
+                args,
+                &["--min", "--max", "--mindepth", "--maxdepth", "--name"],
+            )?;
+            if !rest.is_empty() {
+                return Err(
+                    "usage: find [--min N] [--max N] [--mindepth N] [--maxdepth N] [--name GLOB]"
+                        .to_string(),
+                );
+            }
+
+            let filter = DirFilter {
+                min_size: flag_num(&flags, "--min")?,
+                max_size: flag_num(&flags, "--max")?,
+                min_depth: flag_num(&flags, "--mindepth")?,
+                max_depth: flag_num(&flags, "--maxdepth")?,
+                name: flags.get("--name").map(|s| s.to_string()),
+            };
+            Ok(tree
+                .find(&filter)
+                .iter()
+                .map(|&id| format!("{}\t{}\n", tree.dirsize(id), tree.path(id)))
+                .collect())
+        }
+        "json" | "ncdu" | "transcript" if !args.is_empty() => {
+            Err(format!("{} takes no arguments", cmd))
+        }
+        "json" => Ok(format!("{}\n", tree.to_json(ROOT)?)),
+        "ncdu" => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            Ok(format!("{}\n", tree.to_ncdu(now)?))
+        }
+        "transcript" => Ok(tree.to_transcript()),
+        _ => Err(format!("unknown command {}", cmd)),
+    }
+}
+
+const DISK_SIZE: usize = 70000000;
+
+// Free space the update needs
+const UPDATE_SIZE: usize = 30000000;
+
+// Deepest nesting of arrays and objects serde_json reads back with its default
+// recursion limit
+const JSON_NESTING: usize = 127;
+
+// Names in `dir` starting with `prefix`, directories with a trailing '/'
+fn complete_in(tree: &Tree, dir: NodeId, prefix: &str, dirs_only: bool) -> Vec<String> {
+    let node = &tree.nodes[dir];
+    let mut names: Vec<String> = node
+        .children
+        .iter()
+        .map(|&c| format!("{}/", tree.nodes[c].name))
+        .chain(
+            node.files
+                .iter()
+                .filter(|_| !dirs_only)
+                .map(|f| f.name.clone()),
+        )
+        .filter(|n| n.starts_with(prefix))
+        .collect();
+    names.sort();
+    names
+}
+
+// Completions for the last word of a partly typed command line
+fn complete(tree: &Tree, cwd: NodeId, line: &str) -> Vec<String> {
+    let word = line.rsplit(' ').next().unwrap_or("");
+    let dirs_only = line.starts_with("cd ");
+
+    let (dir_part, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let dir = if dir_part.is_empty() {
+        Some(cwd)
+    } else {
+        tree.find_dir(cwd, dir_part)
+    };
+
+    dir.map_or(Vec::new(), |dir| {
+        complete_in(tree, dir, prefix, dirs_only)
+            .into_iter()
+            .map(|n| format!("{}{}", dir_part, n))
+            .collect()
+    })
 }
 
-fn part1(root: NodeRef) -> usize {
-    let sum_sizes = root.borrow().sum_under_limit(100000);
+// One shell command. Ok(false) means the session is over.
+fn shell_command(
+    tree: &Tree,
+    cwd: &mut NodeId,
+    line: &str,
+    out: &mut impl Write,
+) -> Result<bool, String> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    let w = |out: &mut dyn Write, text: String| {
+        out.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    };
+
+    match args[..] {
+        [] => {}
+        ["exit"] | ["quit"] => return Ok(false),
+        ["help"] => w(
+            out,
+            "cd [PATH], ls [-l] [PATH], pwd, free BYTES [DISK], du [-d N] [PATH], tree [-d N] [PATH], size PATH, find ..., exit\n"
+                .to_string(),
+        )?,
+        ["pwd"] => w(out, format!("{}\n", tree.path(*cwd)))?,
+        ["cd"] => *cwd = ROOT,
+        ["cd", path] => {
+            *cwd = tree
+                .find_dir(*cwd, path)
+                .ok_or(format!("cd: no such directory {}", path))?
+        }
+        ["ls", ref rest @ ..] => {
+            let (long, rest) = match rest {
+                ["-l", rest @ ..] => (true, rest),
+                _ => (false, rest),
+            };
+            let dir = match rest {
+                [] => *cwd,
+                [path] => tree
+                    .find_dir(*cwd, path)
+                    .ok_or(format!("ls: no such directory {}", path))?,
+                _ => return Err("usage: ls [-l] [PATH]".to_string()),
+            };
+
+            for name in complete_in(tree, dir, "", false) {
+                if !long {
+                    w(out, format!("{}\n", name))?;
+                    continue;
+                }
+                let size = tree.path_size(dir, &name).unwrap_or(0);
+                let kind = if name.ends_with('/') { 'd' } else { '-' };
+                w(out, format!("{} {:>10} {}\n", kind, size, name))?;
+            }
+        }
+        ["free", wanted, ref disk @ ..] => {
+            let parse = |v: &str| v.parse::<usize>().map_err(|_| format!("free: bad number {:?}", v));
+            let disk_size = match disk {
+                [] => DISK_SIZE,
+                [d] => parse(d)?,
+                _ => return Err("usage: free BYTES [DISK]".to_string()),
+            };
+
+            match tree.dir_to_free(disk_size, parse(wanted)?)? {
+                Some(id) => w(out, format!("delete {} to free {}\n", tree.path(id), tree.dirsize(id)))?,
+                None => w(out, "nothing to delete\n".to_string())?,
+            }
+        }
+        _ => w(out, run_report(tree, *cwd, &args)?)?,
+    }
+
+    Ok(true)
+}
+
+// Interactive shell over a parsed tree. A line ending in a tab lists completions for
+// its last word instead of running it, which works both from a terminal and from a
+// script piped into stdin.
+fn shell(tree: &Tree, input: impl BufRead, mut out: impl Write, prompt: bool) -> io::Result<()> {
+    let mut cwd = ROOT;
+    let show_prompt = |out: &mut dyn Write, cwd: NodeId| -> io::Result<()> {
+        if prompt {
+            write!(out, "{}$ ", tree.path(cwd))?;
+            out.flush()?;
+        }
+        Ok(())
+    };
+
+    show_prompt(&mut out, cwd)?;
+    for line in input.lines() {
+        let line = line?;
+
+        if let Some(partial) = line.strip_suffix('\t') {
+            writeln!(out, "{}", complete(tree, cwd, partial).join(" "))?;
+        } else {
+            match shell_command(tree, &mut cwd, &line, &mut out) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+        }
+        show_prompt(&mut out, cwd)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sample() {
+    let tree = Tree::init_tree(TEST).unwrap();
+
+    assert_eq!(tree.dirsize(ROOT), 48381165);
+    assert_eq!(tree.dirsize(tree.get_child(ROOT, "d").unwrap()), 24933642);
+    assert_eq!(part1(&tree), 95437);
+    assert_eq!(part2(&tree), 24933642);
+}
+
+#[test]
+fn test_many_dirs() {
+    // 200k directories, half nested in a chain and half side by side
+    let mut transcript = String::from("$ cd /\n$ ls\ndir wide\ndir deep\n$ cd wide\n$ ls\n");
+    for i in 0..100_000 {
+        transcript.push_str(&format!("dir w{}\n", i));
+    }
+    for i in 0..100_000 {
+        transcript.push_str(&format!("$ cd w{}\n$ ls\n1 f\n$ cd ..\n", i));
+    }
+    transcript.push_str("$ cd ..\n$ cd deep\n");
+    for _ in 0..100_000 {
+        transcript.push_str("$ ls\ndir n\n2 f\n$ cd n\n");
+    }
+
+    let tree = Tree::init_tree(&transcript).unwrap();
+
+    assert_eq!(tree.nodes.len(), 200_003);
+    assert_eq!(tree.dirsize(tree.get_child(ROOT, "wide").unwrap()), 100_000);
+    assert_eq!(tree.dirsize(tree.get_child(ROOT, "deep").unwrap()), 200_000);
+    assert_eq!(tree.dirsize(ROOT), 300_000);
+}
+
+#[test]
+fn test_reports() {
+    let tree = Tree::init_tree(TEST).unwrap();
+
+    assert_eq!(
+        run_report(&tree, ROOT, &["tree"]).unwrap(),
+        "48381165 /
+├── 24933642 d/
+│   ├── 8033020 d.log
+│   ├── 7214296 k
+│   ├── 5626152 d.ext
+│   └── 4060174 j
+├── 14848514 b.txt
+├── 8504156 c.dat
+└── 94853 a/
+    ├── 62596 h.lst
+    ├── 29116 f
+    ├── 2557 g
+    └── 584 e/
+        └── 584 i
+"
+    );
+    assert_eq!(
+        run_report(&tree, ROOT, &["tree", "-d", "1", "a"]).unwrap(),
+        "94853 /a/\n├── 62596 h.lst\n├── 29116 f\n├── 2557 g\n└── 584 e/\n"
+    );
+
+    // -d 0 is just the starting directory, the same in both reports
+    assert_eq!(
+        run_report(&tree, ROOT, &["tree", "-d", "0", "a"]).unwrap(),
+        "94853 /a/\n"
+    );
+    assert_eq!(
+        run_report(&tree, ROOT, &["du", "-d", "0", "a"]).unwrap(),
+        "94853\t/a/\n"
+    );
+
+    assert_eq!(
+        run_report(&tree, ROOT, &["du", "-d", "1"]).unwrap(),
+        "94853\t/a/\n24933642\t/d/\n48381165\t/\n"
+    );
+    assert_eq!(
+        run_report(&tree, ROOT, &["du"]).unwrap(),
+        "584\t/a/e/\n94853\t/a/\n24933642\t/d/\n48381165\t/\n"
+    );
+
+    let a = tree.find_dir(ROOT, "/a").unwrap();
+    assert_eq!(run_report(&tree, a, &["size", "e"]).unwrap(), "584\n");
+    assert_eq!(run_report(&tree, a, &["size", "e/i"]).unwrap(), "584\n");
+    assert_eq!(
+        run_report(&tree, a, &["size", "/d/k"]).unwrap(),
+        "7214296\n"
+    );
+    assert_eq!(
+        run_report(&tree, a, &["size", "../b.txt"]).unwrap(),
+        "14848514\n"
+    );
+    assert!(run_report(&tree, a, &["size", "nope"]).is_err());
+
+    assert_eq!(
+        run_report(&tree, ROOT, &["find", "--max", "100000"]).unwrap(),
+        "94853\t/a/\n584\t/a/e/\n"
+    );
+    assert_eq!(
+        run_report(&tree, ROOT, &["find", "--mindepth", "1", "--name", "?"]).unwrap(),
+        "94853\t/a/\n24933642\t/d/\n584\t/a/e/\n"
+    );
+    assert_eq!(
+        run_report(
+            &tree,
+            ROOT,
+            &["find", "--min", "1000", "--maxdepth", "1", "--name", "*"]
+        )
+        .unwrap(),
+        "48381165\t/\n94853\t/a/\n24933642\t/d/\n"
+    );
+}
+
+#[test]
+fn test_json_round_trip() {
+    let tree = Tree::init_tree(TEST).unwrap();
+
+    let nested = tree.to_json(ROOT).unwrap();
+    assert_eq!(nested["size"], 48381165);
+    assert_eq!(nested["children"][0]["name"], "a");
+    assert_eq!(
+        nested["children"][0]["children"][0]["children"][0]["name"],
+        "i"
+    );
+    assert_eq!(
+        tree.to_json(tree.find_dir(ROOT, "/a/e").unwrap()).unwrap()["size"],
+        584
+    );
+
+    let ncdu = tree.to_ncdu(1670400000).unwrap();
+    assert_eq!(ncdu[0], 1);
+    assert_eq!(ncdu[3][0]["name"], "/");
+    assert_eq!(ncdu[3][1][1][0]["name"], "e");
+    assert_eq!(ncdu[3][3]["asize"], 14848514);
+
+    // Both formats load back, and their transcripts parse into the same sizes
+    for value in [nested, ncdu] {
+        let text = serde_json::to_string(&value).unwrap();
+        let loaded = Tree::from_json(&serde_json::from_str(&text).unwrap()).unwrap();
+        let transcript = loaded.to_transcript();
+        let reparsed = Tree::init_tree(&transcript).unwrap();
+
+        assert_eq!(transcript, tree.to_transcript());
+        assert_eq!(reparsed.sizes, tree.sizes);
+        assert_eq!(part1(&reparsed), 95437);
+    }
+
+    let bad = json!({"name": "/", "type": "dir", "children": [{"name": "a b", "size": 1}]});
+    assert!(Tree::from_json(&bad).is_err());
+
+    // As deep as each format goes and still loads back, then one level too deep
+    let chain = |levels: usize| {
+        let mut t = String::from("$ cd /\n");
+        for _ in 0..levels {
+            t += "$ ls\ndir a\n$ cd a\n";
+        }
+        Tree::init_tree(&(t + "$ ls\n1 f\n")).unwrap()
+    };
+    let reload = |value: Value| {
+        let text = serde_json::to_string(&value).unwrap();
+        Tree::from_json(&serde_json::from_str(&text).unwrap()).map(|t| t.nodes.len())
+    };
+    assert_eq!(reload(chain(62).to_json(ROOT).unwrap()), Ok(63));
+    assert_eq!(
+        chain(63).to_json(ROOT).unwrap_err(),
+        "too deep for JSON, it would nest 129 levels and the limit is 127"
+    );
+    assert_eq!(reload(chain(124).to_ncdu(0).unwrap()), Ok(125));
+    assert!(chain(125).to_ncdu(0).is_err());
+}
+
+#[test]
+fn test_materialize_round_trip() {
+    let target = std::env::temp_dir().join(format!("day7-materialize-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&target);
+
+    let tree = Tree::init_tree(TEST).unwrap();
+    tree.materialize(&target).unwrap();
+
+    assert_eq!(fs::metadata(target.join("d/k")).unwrap().len(), 7214296);
+    assert_eq!(fs::metadata(target.join("a/e/i")).unwrap().len(), 584);
+    assert!(tree.materialize(&target).is_err());
+
+    let scanned = Tree::scan_dir(&target).unwrap();
+    let reparsed = Tree::init_tree(&scanned.to_transcript()).unwrap();
+    fs::remove_dir_all(&target).unwrap();
+
+    // Same directories with the same sizes, whatever order the scan found them in
+    for t in [&scanned, &reparsed] {
+        for path in ["/", "/a", "/a/e", "/d"] {
+            let want = tree.dirsize(tree.find_dir(ROOT, path).unwrap());
+            assert_eq!(t.dirsize(t.find_dir(ROOT, path).unwrap()), want, "{}", path);
+        }
+        assert_eq!(t.nodes.len(), tree.nodes.len());
+        assert_eq!(part1(t), 95437);
+        assert_eq!(part2(t), 24933642);
+    }
+}
+
+#[test]
+fn test_shell_script() {
+    let tree = Tree::init_tree(TEST).unwrap();
+    let script = "pwd
+cd a
+ls -l
+cd e
+pwd
+cd ../..
+ls
+cd /a/e/..
+du -d 1
+free 30000000
+free 10 50000000
+free 30000000 10
+cd nope
+cd
+cd \t
+ls d/d\t
+cd /d
+size k
+exit
+pwd
+";
+    let mut out = Vec::new();
+    shell(&tree, script.as_bytes(), &mut out, false).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "/
+d        584 e/
+-      29116 f
+-       2557 g
+-      62596 h.lst
+/a/e/
+a/
+b.txt
+c.dat
+d/
+584\t/a/e/
+94853\t/a/
+delete /d/ to free 24933642
+nothing to delete
+error: can't free 30000000 bytes on a 10 byte disk
+error: cd: no such directory nope
+a/ d/
+d/d.ext d/d.log
+7214296
+"
+    );
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("*", ""));
+    assert!(glob_match("a*c", "abbbc"));
+    assert!(glob_match("a*b*c", "aXbYbZc"));
+    assert!(glob_match("?.txt", "b.txt"));
+    assert!(!glob_match("?.txt", "bb.txt"));
+    assert!(!glob_match("a*c", "abcd"));
+}
+
+#[test]
+fn test_interpreter_paths() {
+    let transcript = "$ cd /
+$ ls
+dir a
+1 x
+$ cd a/b
+$ ls
+10 y
+$ cd /
+$ ls
+dir a
+1 x
+$ cd /a/b/../c
+$ ls
+100 z
+$ cd ../b
+$ ls
+10 y
+$ cd /a
+$ cd ./b
+$ cd ../../..";
+
+    let tree = Tree::init_tree(transcript).unwrap();
+    let a = tree.get_child(ROOT, "a").unwrap();
+    let b = tree.get_child(a, "b").unwrap();
+    let c = tree.get_child(a, "c").unwrap();
+
+    // Repeated ls of / and b did not duplicate anything
+    assert_eq!(tree.nodes.len(), 4);
+    assert_eq!(tree.nodes[ROOT].files.len(), 1);
+    assert_eq!(tree.nodes[b].files.len(), 1);
+    assert_eq!(tree.path(c), "/a/c/");
+    assert_eq!(tree.dirsize(a), 110);
+    assert_eq!(tree.dirsize(ROOT), 111);
+}
+
+#[test]
+fn test_interpreter_errors() {
+    let both = Tree::init_tree("$ cd /\n$ ls\ndir a\n$ cd a\n$ ls\n5 b\n$ cd ..\n$ cd a/b");
+    assert_eq!(
+        both.unwrap_err(),
+        "line 8: /a/b listed as both a file and a directory"
+    );
+
+    let both = Tree::init_tree("$ ls\n5 a\ndir a");
+    assert_eq!(
+        both.unwrap_err(),
+        "line 3: /a listed as both a file and a directory"
+    );
+
+    let resized = Tree::init_tree("$ cd /\n$ ls\n5 a\n$ ls\n6 a");
+    assert_eq!(resized.unwrap_err(), "line 5: /a changed size from 5 to 6");
+
+    let stray = Tree::init_tree("$ cd /\n5 a");
+    assert_eq!(stray.unwrap_err(), "line 2: output outside of ls \"5 a\"");
+
+    let escape = Tree::init_tree("$ cd /\n$ ls\n5 ../escape");
+    assert_eq!(
+        escape.unwrap_err(),
+        "line 3: /\"../escape\" is not a valid name"
+    );
+    let absolute = Tree::init_tree("$ cd /\n$ ls\n5 /etc/x");
+    assert_eq!(
+        absolute.unwrap_err(),
+        "line 3: /\"/etc/x\" is not a valid name"
+    );
+    let dotdot = Tree::init_tree("$ cd /\n$ ls\ndir ..");
+    assert_eq!(dotdot.unwrap_err(), "line 3: /\"..\" is not a valid name");
+
+    let unknown = Tree::init_tree("$ rm -rf /");
+    assert_eq!(
+        unknown.unwrap_err(),
+        "line 1: unknown command \"$ rm -rf /\""
+    );
+}
+
+fn part1(tree: &Tree) -> usize {
+    let sum_sizes = tree.sum_under_limit(100000);
     println!("part1: sum_sizes: {}", sum_sizes);
-    return sum_sizes;
+    sum_sizes
 }
 
-fn part2(root: NodeRef) -> usize {
-    let disk_size: usize = 70000000;
-    let update_size_needed: usize = 30000000;
-    let total_size = root.borrow().dirsize(0); // Disk space used
-    let need_space = update_size_needed - (disk_size - total_size); // File to delete size
-    let smallest = root.borrow().largest_under_limit(need_space);
+fn part2(tree: &Tree) -> usize {
+    let smallest = match tree.dir_to_free(DISK_SIZE, UPDATE_SIZE) {
+        Ok(Some(id)) => tree.dirsize(id),
+        _ => 0,
+    };
     println!("smallest dir size to delete: {}", smallest);
     smallest
 }
 
+// day7 [-i TRANSCRIPT|JSON] [tree|du|size|find|json|ncdu|transcript ...]
+// day7 [-i TRANSCRIPT|JSON] materialize DIR
+// day7 [-i TRANSCRIPT|JSON] shell
+// day7 scan DIR
 fn main() -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string("src/puzzle.txt")?;
-    let root = Node::init_tree(Node::new_noderef(Node::new("/")), contents.as_str());
-    part1(root.clone());
-    part2(root.clone());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+    if let ["scan", dir] = args[..] {
+        print!("{}", Tree::scan_dir(Path::new(dir))?.to_transcript());
+        return Ok(());
+    }
+
+    let mut input = "src/puzzle.txt";
+    if let ["-i", path, ..] = args[..] {
+        input = path;
+        args.drain(..2);
+    }
+
+    let contents = fs::read_to_string(input)?;
+    let tree = if contents.trim_start().starts_with(['{', '[']) {
+        Tree::from_json(&serde_json::from_str(&contents)?)?
+    } else {
+        Tree::init_tree(contents.as_str())?
+    };
+
+    if let ["shell"] = args[..] {
+        let stdin = io::stdin();
+        let prompt = stdin.is_terminal();
+        shell(&tree, stdin.lock(), io::stdout(), prompt)?;
+        return Ok(());
+    }
+
+    if let ["materialize", dir] = args[..] {
+        tree.materialize(Path::new(dir))?;
+        eprintln!("{} directories written to {}", tree.nodes.len(), dir);
+        return Ok(());
+    }
+
+    if !args.is_empty() {
+        print!("{}", run_report(&tree, ROOT, &args)?);
+        return Ok(());
+    }
+
+    part1(&tree);
+    part2(&tree);
     Ok(())
 }
@@ -1,38 +1,66 @@
-use itertools::Itertools;
 use std::error::Error;
 use std::fs;
+use std::path::Path;
+#[cfg(test)]
 use take_until::TakeUntilExt;
 
-const TESTGRID: &str = &"30373
+#[cfg(test)]
+const TESTGRID: &str = "30373
 25512
 65332
 33549
 35390";
 
-fn parse_grid(gstr: &str) -> Vec<Vec<u32>> {
-    let lines = gstr.split("\n").collect::<Vec<&str>>();
-    let grid: Vec<Vec<u32>> = Vec::new();
-    lines
+// Rows of tree heights, every row has to be as long as the first
+fn parse_grid(gstr: &str) -> Result<Vec<Vec<u32>>, String> {
+    let lines = gstr
+        .lines()
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<&str>>();
+    let grid = lines
         .iter()
         .map(|line| {
             line.chars()
                 .filter_map(|c| c.to_digit(10))
                 .collect::<Vec<u32>>()
         })
-        .collect::<Vec<Vec<u32>>>()
+        .collect::<Vec<Vec<u32>>>();
+
+    let cols = grid.first().ok_or("no trees in the grid")?.len();
+    if let Some((y, row)) = grid.iter().enumerate().find(|(_, r)| r.len() != cols) {
+        return Err(format!(
+            "row {} has {} trees, the first row has {}",
+            y + 1,
+            row.len(),
+            cols
+        ));
+    }
+    Ok(grid)
 }
 
+// Trees on the outer ring. Grids less than 3 wide or tall have no interior at all.
+fn edge_count(rows: usize, cols: usize) -> usize {
+    if rows < 3 || cols < 3 {
+        rows * cols
+    } else {
+        cols * 2 + (rows - 2) * 2
+    }
+}
+
+// The original ray scans: walk out from every tree in each direction. O(n) per tree, so
+// O(n^3) over the grid, kept as the reference the sweeps below are checked against.
+#[cfg(test)]
 fn vec_vis(tree: &u32, tree_row: &[u32], rev: bool) -> bool {
     if rev {
         tree_row.iter().rev().fold(true, |acc, some_tree| {
-            if some_tree < &tree && acc {
+            if some_tree < tree && acc {
                 return true;
             }
             false
         })
     } else {
         tree_row.iter().fold(true, |acc, some_tree| {
-            if some_tree < &tree && acc {
+            if some_tree < tree && acc {
                 return true;
             }
             false
@@ -40,34 +68,29 @@ fn vec_vis(tree: &u32, tree_row: &[u32], rev: bool) -> bool {
     }
 }
 
+#[cfg(test)]
 fn vec_vis_cnt(tree: &u32, tree_row: &[u32], rev: bool) -> u32 {
-    let mut cnt = 0;
-
     if rev {
-        cnt = tree_row
+        tree_row
             .iter()
             .rev()
-            .take_until(|z| *z >= &tree)
-            .fold(0, |acc, z| acc + 1);
+            .take_until(|z| *z >= tree)
+            .fold(0, |acc, _| acc + 1)
     } else {
-        cnt = tree_row
+        tree_row
             .iter()
-            .take_until(|z| *z >= &tree)
-            .fold(0, |acc, z| acc + 1);
+            .take_until(|z| *z >= tree)
+            .fold(0, |acc, _| acc + 1)
     }
-
-    cnt
 }
 
-fn get_scenic_score(x: usize, y: usize, grid: &Vec<Vec<u32>>) -> u32 {
+#[cfg(test)]
+fn get_scenic_score(x: usize, y: usize, grid: &[Vec<u32>]) -> u32 {
     let cur_tree = grid[y][x];
     let r = &grid[y][x + 1..];
     let l = &grid[y][..x];
 
-    let mut col: Vec<u32> = Vec::new();
-    for i in 0..grid[x].len() {
-        col.push(grid[i][x]);
-    }
+    let col: Vec<u32> = grid.iter().map(|row| row[x]).collect();
     let u = &col[..y];
     let d = &col[y + 1..];
 
@@ -81,18 +104,16 @@ fn get_scenic_score(x: usize, y: usize, grid: &Vec<Vec<u32>>) -> u32 {
     //     y, x, &cur_tree, &uscore, &dscore, &lscore, &rscore
     // );
 
-    return rscore * lscore * dscore * uscore;
+    rscore * lscore * dscore * uscore
 }
 
-fn test_vis(x: usize, y: usize, grid: &Vec<Vec<u32>>) -> bool {
+#[cfg(test)]
+fn test_vis(x: usize, y: usize, grid: &[Vec<u32>]) -> bool {
     let cur_tree = grid[y][x];
     let r = &grid[y][x + 1..];
     let l = &grid[y][..x];
 
-    let mut col: Vec<u32> = Vec::new();
-    for i in 0..grid[x].len() {
-        col.push(grid[i][x]);
-    }
+    let col: Vec<u32> = grid.iter().map(|row| row[x]).collect();
     let u = &col[..y];
     let d = &col[y + 1..];
 
@@ -101,40 +122,886 @@ fn test_vis(x: usize, y: usize, grid: &Vec<Vec<u32>>) -> bool {
     let dtrue = vec_vis(&cur_tree, d, false);
     let utrue = vec_vis(&cur_tree, u, true);
 
-    if ltrue || rtrue || dtrue || utrue {
-        return true;
+    ltrue || rtrue || dtrue || utrue
+}
+
+// Viewing distance from one tree in each direction, indexed by UP, DOWN, LEFT, RIGHT
+type Views = [u32; 4];
+
+const UP: usize = 0;
+const DOWN: usize = 1;
+const LEFT: usize = 2;
+const RIGHT: usize = 3;
+
+// Visible from outside the grid: some sweep from an edge reaches the tree while every
+// tree passed so far was shorter. Four passes with a running max, O(rows * cols).
+fn visibility(grid: &[Vec<u32>]) -> Vec<Vec<bool>> {
+    let rows = grid.len();
+    let cols = grid.first().map_or(0, |r| r.len());
+    let mut vis = vec![vec![false; cols]; rows];
+
+    let mut sweep = |cells: &mut dyn Iterator<Item = (usize, usize)>| {
+        let mut tallest: Option<u32> = None;
+        for (y, x) in cells {
+            if tallest.is_none_or(|t| grid[y][x] > t) {
+                vis[y][x] = true;
+                tallest = Some(grid[y][x]);
+            }
+        }
+    };
+
+    for y in 0..rows {
+        sweep(&mut (0..cols).map(|x| (y, x)));
+        sweep(&mut (0..cols).rev().map(|x| (y, x)));
+    }
+    for x in 0..cols {
+        sweep(&mut (0..rows).map(|y| (y, x)));
+        sweep(&mut (0..rows).rev().map(|y| (y, x)));
     }
 
-    false
+    vis
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string("src/puzzle.txt")?;
-    let grid = parse_grid(contents.as_str());
+// Viewing distance of every tree in every direction. Each line is swept once keeping a
+// stack of trees that could still block the view: anything shorter than the current
+// tree can never block a later one past it, so it is popped. Every tree is pushed and
+// popped at most once per sweep, so O(rows * cols) overall.
+fn viewing_distances(grid: &[Vec<u32>]) -> Vec<Vec<Views>> {
+    let rows = grid.len();
+    let cols = grid.first().map_or(0, |r| r.len());
+    let mut views = vec![vec![[0; 4]; cols]; rows];
+
+    // `cells` walks a line away from the edge we look towards, `dir` is that edge
+    let mut sweep = |cells: Vec<(usize, usize)>, dir: usize| {
+        let mut stack: Vec<usize> = Vec::new();
+        for (i, &(y, x)) in cells.iter().enumerate() {
+            while let Some(&top) = stack.last() {
+                let (ty, tx) = cells[top];
+                if grid[ty][tx] >= grid[y][x] {
+                    break;
+                }
+                stack.pop();
+            }
+            // Blocked by the first tree at least as tall, or sees all the way to the edge
+            views[y][x][dir] = (i - stack.last().map_or(0, |&top| top)) as u32;
+            stack.push(i);
+        }
+    };
+
+    for y in 0..rows {
+        sweep((0..cols).map(|x| (y, x)).collect(), LEFT);
+        sweep((0..cols).rev().map(|x| (y, x)).collect(), RIGHT);
+    }
+    for x in 0..cols {
+        sweep((0..rows).map(|y| (y, x)).collect(), UP);
+        sweep((0..rows).rev().map(|y| (y, x)).collect(), DOWN);
+    }
+
+    views
+}
+
+fn scenic_scores(grid: &[Vec<u32>]) -> Vec<Vec<u32>> {
+    viewing_distances(grid)
+        .iter()
+        .map(|row| row.iter().map(|v| v.iter().product()).collect())
+        .collect()
+}
+
+// Directions as (dx, dy), the first four line up with UP, DOWN, LEFT, RIGHT
+const DIRS: [(isize, isize); 8] = [
+    (0, -1),
+    (0, 1),
+    (-1, 0),
+    (1, 0),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+    (1, 1),
+];
+
+// How the per-direction viewing distances combine into one scenic score
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Product,
+    Sum,
+    Min,
+}
+
+impl Aggregate {
+    fn parse(s: &str) -> Option<Aggregate> {
+        match s {
+            "product" => Some(Aggregate::Product),
+            "sum" => Some(Aggregate::Sum),
+            "min" => Some(Aggregate::Min),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, views: &[u32]) -> u64 {
+        let views = views.iter().map(|v| *v as u64);
+        match self {
+            Aggregate::Product => views.product(),
+            Aggregate::Sum => views.sum(),
+            Aggregate::Min => views.min().unwrap_or(0),
+        }
+    }
+}
+
+// Knobs for what a tree can see. The default is the puzzle's rules.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SightRules {
+    // Look along the four diagonals as well
+    diagonals: bool,
+    // A tree of height t blocks a viewer of height h when t >= h + tolerance. 0 is the
+    // puzzle, 1 also sees past trees of equal height, -k means trees up to k shorter
+    // than the viewer already block it.
+    tolerance: i32,
+    // Viewing distances are capped at this many trees. Visibility from outside the
+    // grid is not affected.
+    max_range: Option<u32>,
+    aggregate: Aggregate,
+}
+
+impl Default for SightRules {
+    fn default() -> Self {
+        SightRules {
+            diagonals: false,
+            tolerance: 0,
+            max_range: None,
+            aggregate: Aggregate::Product,
+        }
+    }
+}
+
+impl SightRules {
+    fn dirs(&self) -> &'static [(isize, isize)] {
+        if self.diagonals {
+            &DIRS
+        } else {
+            &DIRS[..4]
+        }
+    }
+
+    fn blocks(&self, viewer: u32, other: u32) -> bool {
+        other as i64 >= viewer as i64 + self.tolerance as i64
+    }
+}
+
+// Everything the rules give for each tree: visible from outside, viewing distance per
+// direction of `rules.dirs()`, and the aggregated score
+struct Survey {
+    visible: Vec<Vec<bool>>,
+    views: Vec<Vec<Vec<u32>>>,
+    scores: Vec<Vec<u64>>,
+}
+
+// Walk out from (x, y) until the edge or the first tree that blocks. Returns whether the
+// edge was reached and how many trees were seen on the way, the blocker included.
+fn ray(
+    grid: &[Vec<u32>],
+    x: usize,
+    y: usize,
+    (dx, dy): (isize, isize),
+    rules: &SightRules,
+) -> (bool, u32) {
+    let h = grid[y][x];
+    let (mut cx, mut cy) = (x as isize, y as isize);
+    let mut seen = 0;
+
+    loop {
+        cx += dx;
+        cy += dy;
+        if cy < 0 || cy as usize >= grid.len() || cx < 0 || cx as usize >= grid[cy as usize].len() {
+            return (true, seen);
+        }
+        seen += 1;
+        if rules.blocks(h, grid[cy as usize][cx as usize]) {
+            return (false, seen);
+        }
+    }
+}
+
+// The puzzle's rules go through the linear sweeps, anything else walks every ray
+fn survey(grid: &[Vec<u32>], rules: &SightRules) -> Survey {
+    let plain = SightRules {
+        aggregate: rules.aggregate,
+        max_range: rules.max_range,
+        ..SightRules::default()
+    };
+
+    let (visible, mut views): (Vec<Vec<bool>>, Vec<Vec<Vec<u32>>>) = if *rules == plain {
+        let views = viewing_distances(grid)
+            .iter()
+            .map(|row| row.iter().map(|v| v.to_vec()).collect())
+            .collect();
+        (visibility(grid), views)
+    } else {
+        let mut visible = Vec::new();
+        let mut views = Vec::new();
+        for (y, row) in grid.iter().enumerate() {
+            let rays: Vec<Vec<(bool, u32)>> = (0..row.len())
+                .map(|x| {
+                    rules
+                        .dirs()
+                        .iter()
+                        .map(|d| ray(grid, x, y, *d, rules))
+                        .collect()
+                })
+                .collect();
+            visible.push(
+                rays.iter()
+                    .map(|r| r.iter().any(|(edge, _)| *edge))
+                    .collect(),
+            );
+            views.push(
+                rays.iter()
+                    .map(|r| r.iter().map(|(_, n)| *n).collect())
+                    .collect(),
+            );
+        }
+        (visible, views)
+    };
+
+    if let Some(range) = rules.max_range {
+        for v in views.iter_mut().flatten().flatten() {
+            *v = (*v).min(range);
+        }
+    }
+
+    let scores = views
+        .iter()
+        .map(|row: &Vec<Vec<u32>>| row.iter().map(|v| rules.aggregate.apply(v)).collect())
+        .collect();
+
+    Survey {
+        visible,
+        views,
+        scores,
+    }
+}
+
+// Rectangle of trees, corners included
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Region {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl Region {
+    // "x0,y0,x1,y1"
+    fn parse(s: &str) -> Option<Region> {
+        let n: Vec<usize> = s
+            .split(',')
+            .map(|v| v.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match n[..] {
+            [x0, y0, x1, y1] => Some(Region {
+                x0: x0.min(x1),
+                y0: y0.min(y1),
+                x1: x0.max(x1),
+                y1: y0.max(y1),
+            }),
+            _ => None,
+        }
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x0..=self.x1).contains(&x) && (self.y0..=self.y1).contains(&y)
+    }
+}
+
+// Where the tree house may go
+#[derive(Debug, Clone, Default)]
+struct PlacementQuery {
+    top: usize,
+    min_height: Option<u32>,
+    visible_only: bool,
+    exclude: Vec<Region>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Placement {
+    x: usize,
+    y: usize,
+    height: u32,
+    score: u64,
+    // Viewing distance per direction of the rules used, see SightRules::dirs
+    views: Vec<u32>,
+}
+
+// The `query.top` best trees under `rules`, highest score first. Ties go to the tree
+// nearer the top, then nearer the left, so the same grid always gives the same list.
+fn best_placements(
+    grid: &[Vec<u32>],
+    rules: &SightRules,
+    query: &PlacementQuery,
+) -> Vec<Placement> {
+    let survey = survey(grid, rules);
+    let mut found: Vec<Placement> = Vec::new();
+
+    for (y, row) in grid.iter().enumerate() {
+        for (x, &height) in row.iter().enumerate() {
+            if query.min_height.is_some_and(|min| height < min)
+                || (query.visible_only && !survey.visible[y][x])
+                || query.exclude.iter().any(|r| r.contains(x, y))
+            {
+                continue;
+            }
+
+            found.push(Placement {
+                x,
+                y,
+                height,
+                score: survey.scores[y][x],
+                views: survey.views[y][x].clone(),
+            });
+        }
+    }
+
+    found.sort_by_key(|p| (std::cmp::Reverse(p.score), p.y, p.x));
+    found.truncate(query.top);
+    found
+}
+
+type Rgb = [u8; 3];
+
+// Plain RGB raster, each tree drawn as a `scale` x `scale` block
+struct Image {
+    width: usize,
+    height: usize,
+    scale: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Image {
+    fn for_grid(grid: &[Vec<u32>], scale: usize) -> Image {
+        let width = grid.first().map_or(0, |r| r.len()) * scale;
+        let height = grid.len() * scale;
+        Image {
+            width,
+            height,
+            scale,
+            pixels: vec![[0, 0, 0]; width * height],
+        }
+    }
 
-    let grid_dim = grid[0].len();
-    let edge_cnt = grid_dim * 2 + (grid_dim - 2) * 2;
-    let mut vis_cnt: u32 = 0;
-    let mut scenic: Vec<u32> = Vec::new();
+    fn fill_tree(&mut self, x: usize, y: usize, color: Rgb) {
+        for py in y * self.scale..(y + 1) * self.scale {
+            for px in x * self.scale..(x + 1) * self.scale {
+                self.pixels[py * self.width + px] = color;
+            }
+        }
+    }
+
+    // Square outline around one tree, drawn one tree wide so it shows at scale 1
+    fn mark_tree(&mut self, x: usize, y: usize, color: Rgb) {
+        let (x0, y0) = ((x * self.scale) as isize, (y * self.scale) as isize);
+        let s = self.scale as isize;
+
+        for i in -s..2 * s {
+            for (px, py) in [
+                (x0 + i, y0 - s),
+                (x0 + i, y0 + 2 * s - 1),
+                (x0 - s, y0 + i),
+                (x0 + 2 * s - 1, y0 + i),
+            ] {
+                if px >= 0 && py >= 0 && (px as usize) < self.width && (py as usize) < self.height {
+                    self.pixels[py as usize * self.width + px as usize] = color;
+                }
+            }
+        }
+    }
+
+    fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        out.extend(self.pixels.iter().flatten());
+        out
+    }
+
+    fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()?
+            .write_image_data(&self.pixels.concat())?;
+        Ok(out)
+    }
+
+    // Format picked from the extension, .ppm or .png
+    fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let bytes = match path.extension().and_then(|e| e.to_str()) {
+            Some("ppm") => self.to_ppm(),
+            Some("png") => self.to_png()?,
+            _ => return Err(format!("{}: expected a .png or .ppm file", path.display()).into()),
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+}
+
+fn gray(height: u32) -> Rgb {
+    let v = (height.min(9) * 255 / 9) as u8;
+    [v, v, v]
+}
+
+// Heights 0-9 as black to white
+fn render_heights(grid: &[Vec<u32>], scale: usize) -> Image {
+    let mut img = Image::for_grid(grid, scale);
+    for (y, row) in grid.iter().enumerate() {
+        for (x, h) in row.iter().enumerate() {
+            img.fill_tree(x, y, gray(*h));
+        }
+    }
+    img
+}
+
+// Heights with the trees visible from outside tinted green
+fn render_visibility(grid: &[Vec<u32>], scale: usize) -> Image {
+    let mut img = Image::for_grid(grid, scale);
+    let vis = visibility(grid);
+    for (y, row) in grid.iter().enumerate() {
+        for (x, h) in row.iter().enumerate() {
+            let [v, _, _] = gray(*h);
+            let color = if vis[y][x] {
+                [v / 3, 96 + v / 2, v / 3]
+            } else {
+                [v, v, v]
+            };
+            img.fill_tree(x, y, color);
+        }
+    }
+    img
+}
+
+// Black -> blue -> red -> yellow for t in 0..=1
+fn heat(t: f64) -> Rgb {
+    let t = t.clamp(0.0, 1.0);
+    let (r, g, b) = if t < 1.0 / 3.0 {
+        (0.0, 0.0, t * 3.0)
+    } else if t < 2.0 / 3.0 {
+        let u = t * 3.0 - 1.0;
+        (u, 0.0, 1.0 - u)
+    } else {
+        (1.0, t * 3.0 - 2.0, 0.0)
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
+
+// Scenic scores as a heatmap with the best tree outlined in white. Scores span several
+// orders of magnitude so the colour follows the square root to keep the low end visible.
+fn render_scenic(grid: &[Vec<u32>], scale: usize) -> Image {
+    let mut img = Image::for_grid(grid, scale);
+    let scores = scenic_scores(grid);
+    let query = PlacementQuery {
+        top: 1,
+        ..PlacementQuery::default()
+    };
+    let best = best_placements(grid, &SightRules::default(), &query).pop();
+
+    let max = best.as_ref().map_or(0, |b| b.score).max(1) as f64;
+    for (y, row) in scores.iter().enumerate() {
+        for (x, s) in row.iter().enumerate() {
+            img.fill_tree(x, y, heat((*s as f64 / max).sqrt()));
+        }
+    }
+
+    if let Some(best) = best {
+        img.mark_tree(best.x, best.y, [255, 255, 255]);
+    }
+    img
+}
+
+#[test]
+fn test_sample_grid() {
+    let grid = parse_grid(TESTGRID).unwrap();
+    let visible = visibility(&grid).iter().flatten().filter(|v| **v).count();
+    let scores = scenic_scores(&grid);
+
+    assert_eq!(visible, 21);
+    assert_eq!(scores[3][2], 8);
+    assert_eq!(scores.iter().flatten().max(), Some(&8));
+    assert_eq!(viewing_distances(&grid)[3][2], [2, 1, 2, 2]);
+}
+
+#[test]
+fn test_sight_rules() {
+    let grid = parse_grid(TESTGRID).unwrap();
+
+    // Default rules give the puzzle answers, and walking the rays agrees with the sweeps
+    let plain = survey(&grid, &SightRules::default());
+    assert_eq!(plain.visible.iter().flatten().filter(|v| **v).count(), 21);
+    assert_eq!(plain.scores.iter().flatten().max(), Some(&8));
+    assert_eq!(plain.views[3][2], vec![2, 1, 2, 2]);
+
+    for y in 0..grid.len() {
+        for x in 0..grid[y].len() {
+            let rays: Vec<(bool, u32)> = DIRS[..4]
+                .iter()
+                .map(|d| ray(&grid, x, y, *d, &SightRules::default()))
+                .collect();
+            assert_eq!(plain.visible[y][x], rays.iter().any(|r| r.0));
+            assert_eq!(
+                plain.views[y][x],
+                rays.iter().map(|r| r.1).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    // The 4 at (3,3) is hidden on all four sides but can be seen from three corners
+    let diag = survey(
+        &grid,
+        &SightRules {
+            diagonals: true,
+            ..SightRules::default()
+        },
+    );
+    assert!(!plain.visible[3][3]);
+    assert!(diag.visible[3][3]);
+    assert_eq!(diag.views[3][3], vec![3, 1, 1, 1, 2, 1, 1, 1]);
+    assert_eq!(diag.scores[3][3], 6);
+
+    // Seeing past equal trees: the 5 at (1,1) now sees the whole row
+    let past_equal = survey(
+        &grid,
+        &SightRules {
+            tolerance: 1,
+            ..SightRules::default()
+        },
+    );
+    assert_eq!(past_equal.views[1][1], vec![1, 3, 1, 3]);
+    assert!(past_equal.visible.iter().flatten().filter(|v| **v).count() > 21);
+
+    let capped = survey(
+        &grid,
+        &SightRules {
+            max_range: Some(1),
+            aggregate: Aggregate::Sum,
+            ..SightRules::default()
+        },
+    );
+    assert_eq!(capped.views[3][2], vec![1, 1, 1, 1]);
+    assert_eq!(capped.scores[3][2], 4);
+    assert_eq!(capped.scores[0][0], 2);
+
+    assert_eq!(Aggregate::Min.apply(&[3, 1, 2]), 1);
+    assert_eq!(Aggregate::Sum.apply(&[3, 1, 2]), 6);
+    assert_eq!(Aggregate::Product.apply(&[3, 1, 2]), 6);
+}
+
+#[test]
+fn test_best_placements() {
+    let grid = parse_grid(TESTGRID).unwrap();
+    let rules = SightRules::default();
+    let top = |query: PlacementQuery| {
+        best_placements(&grid, &rules, &query)
+            .iter()
+            .map(|p| (p.score, p.x, p.y))
+            .collect::<Vec<_>>()
+    };
 
-    for y in 1..grid[0].len() - 1 {
-        for x in 1..grid[y].len() - 1 {
-            // eprintln!("grid[{}][{}] = {:?}", y, x, grid[y][x]);
+    let all = best_placements(
+        &grid,
+        &rules,
+        &PlacementQuery {
+            top: 9,
+            ..PlacementQuery::default()
+        },
+    );
+    assert_eq!(all[0].views, vec![2, 1, 2, 2]);
+    assert_eq!(all[0].height, 5);
+    // Four trees score 1, they come top to bottom then left to right
+    assert_eq!(
+        all.iter().map(|p| (p.score, p.x, p.y)).collect::<Vec<_>>(),
+        vec![
+            (8, 2, 3),
+            (6, 1, 2),
+            (4, 2, 1),
+            (3, 3, 3),
+            (2, 3, 2),
+            (1, 1, 1),
+            (1, 3, 1),
+            (1, 2, 2),
+            (1, 1, 3)
+        ]
+    );
 
-            // Part 1
-            if test_vis(x, y, &grid) {
-                vis_cnt += 1;
+    let excluded = top(PlacementQuery {
+        top: 2,
+        exclude: vec![
+            Region::parse("2,3,2,3").unwrap(),
+            Region::parse("0,0,1,2").unwrap(),
+        ],
+        ..PlacementQuery::default()
+    });
+    assert_eq!(excluded, vec![(4, 2, 1), (3, 3, 3)]);
+
+    // At least 5 tall and visible: the interior 5s, then the edge trees which all score 0
+    let tall_visible = top(PlacementQuery {
+        top: 10,
+        min_height: Some(5),
+        visible_only: true,
+        ..PlacementQuery::default()
+    });
+    assert_eq!(
+        tall_visible,
+        vec![
+            (8, 2, 3),
+            (6, 1, 2),
+            (4, 2, 1),
+            (1, 1, 1),
+            (0, 3, 0),
+            (0, 0, 2),
+            (0, 4, 3),
+            (0, 1, 4),
+            (0, 3, 4)
+        ]
+    );
+
+    assert_eq!(
+        Region::parse("3,1,0,2").map(|r| r.contains(2, 2)),
+        Some(true)
+    );
+    assert_eq!(Region::parse("1,2,3"), None);
+}
+
+#[test]
+fn test_render_sample() {
+    let grid = parse_grid(TESTGRID).unwrap();
+
+    let heights = render_heights(&grid, 2);
+    assert_eq!((heights.width, heights.height), (10, 10));
+    assert_eq!(heights.pixels[0], [85, 85, 85]); // 3
+    assert_eq!(heights.pixels[2 * 10 + 2], gray(5));
+
+    // The middle 3 in the centre is hidden, the 5 above it is visible
+    let visible = render_visibility(&grid, 1);
+    assert_eq!(visible.pixels[2 * 5 + 2], gray(3));
+    assert_ne!(visible.pixels[5 + 2], gray(5));
+
+    // Best score is the 5 at row 3, column 2, the outline goes around it
+    let scenic = render_scenic(&grid, 1);
+    assert_eq!(scenic.pixels[3 * 5 + 2], heat(1.0));
+    assert_eq!(scenic.pixels[2 * 5 + 1], [255, 255, 255]);
+    assert_eq!(scenic.pixels[4 * 5 + 3], [255, 255, 255]);
+
+    let ppm = scenic.to_ppm();
+    assert!(ppm.starts_with(b"P6\n5 5\n255\n"));
+    assert_eq!(ppm.len(), 11 + 5 * 5 * 3);
+
+    let png = scenic.to_png().unwrap();
+    let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (5, 5));
+    assert_eq!(
+        &buf[..info.buffer_size()],
+        scenic.pixels.concat().as_slice()
+    );
+}
+
+#[test]
+fn test_sweeps_match_ray_scans() {
+    // Square grids are what the ray scans were written for
+    let puzzle = fs::read_to_string("src/puzzle.txt").unwrap();
+    for grid in [parse_grid(TESTGRID).unwrap(), parse_grid(&puzzle).unwrap()] {
+        let vis = visibility(&grid);
+        let scores = scenic_scores(&grid);
+
+        for y in 1..grid.len() - 1 {
+            for x in 1..grid[y].len() - 1 {
+                assert_eq!(vis[y][x], test_vis(x, y, &grid), "vis {},{}", x, y);
+                assert_eq!(
+                    scores[y][x],
+                    get_scenic_score(x, y, &grid),
+                    "score {},{}",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rectangular_grid() {
+    let grid = parse_grid("3037\n2551\n6533").unwrap();
+    let vis = visibility(&grid);
+    let views = viewing_distances(&grid);
+
+    // Only the middle row has interior trees: 5 and 5 at (1,1) and (2,1)
+    assert_eq!(vis[1], vec![true, true, true, true]);
+    assert_eq!(vis.iter().flatten().filter(|v| **v).count(), 12);
+    assert_eq!(views[1][1], [1, 1, 1, 1]);
+    assert_eq!(views[1][2], [1, 1, 1, 1]);
+    assert_eq!(views[0][3], [0, 2, 3, 0]);
+    assert_eq!(views[2][0], [2, 0, 0, 3]);
+
+    let wide = parse_grid("1234567").unwrap();
+    assert_eq!(visibility(&wide)[0], vec![true; 7]);
+    assert_eq!(viewing_distances(&wide)[0][6], [0, 0, 6, 0]);
+
+    // Every tree of a thin grid is an edge tree
+    assert_eq!(edge_count(1, 7), 7);
+    assert_eq!(edge_count(2, 2), 4);
+    assert_eq!(edge_count(3, 4), 10);
+    assert_eq!(edge_count(5, 5), 16);
+
+    assert_eq!(
+        parse_grid("3037\n255\n6533").unwrap_err(),
+        "row 2 has 3 trees, the first row has 4"
+    );
+    assert_eq!(parse_grid("\n").unwrap_err(), "no trees in the grid");
+}
+
+// SightRules from --diagonals, --tolerance, --range and --score, which may come anywhere
+// on the command line. Other arguments are kept in order for `top` and the grid path.
+fn parse_rules(args: &[String]) -> Result<(SightRules, Vec<String>), Box<dyn Error>> {
+    let mut rules = SightRules::default();
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--diagonals" => rules.diagonals = true,
+            "--tolerance" => rules.tolerance = value()?.parse()?,
+            "--range" => rules.max_range = Some(value()?.parse()?),
+            "--score" => {
+                let v = value()?;
+                rules.aggregate = Aggregate::parse(v).ok_or(format!("unknown score {}", v))?;
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((rules, rest))
+}
+
+// The arguments after `top`: K, then --min-height, --visible and any number of --exclude
+// regions. Whatever is left over should be the grid path.
+fn parse_query(args: &[String]) -> Result<(PlacementQuery, Vec<String>), Box<dyn Error>> {
+    let mut iter = args.iter();
+    let mut query = PlacementQuery {
+        top: iter.next().ok_or("usage: day8 top K ...")?.parse()?,
+        ..PlacementQuery::default()
+    };
+    let mut rest = Vec::new();
+
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--min-height" => query.min_height = Some(value()?.parse()?),
+            "--visible" => query.visible_only = true,
+            "--exclude" => {
+                let v = value()?;
+                query
+                    .exclude
+                    .push(Region::parse(v).ok_or(format!("bad region {}, want x0,y0,x1,y1", v))?);
             }
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((query, rest))
+}
 
-            // Part 2
-            scenic.push(get_scenic_score(x, y, &grid));
+// day8 [--diagonals] [--tolerance K] [--range N] [--score product|sum|min] [GRID]
+// day8 [rules...] top K [--min-height H] [--visible] [--exclude x0,y0,x1,y1]... [GRID]
+// day8 render OUTDIR [png|ppm] [SCALE] [GRID]
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(|a| a.as_str()) == Some("render") {
+        let outdir = Path::new(
+            args.get(1)
+                .ok_or("usage: day8 render OUTDIR [png|ppm] [SCALE] [GRID]")?,
+        );
+        let ext = args.get(2).map_or("png", |e| e.as_str());
+        let scale: usize = args.get(3).map_or(Ok(4), |s| s.parse())?;
+        if scale == 0 {
+            return Err(
+                "usage: day8 render OUTDIR [png|ppm] [SCALE] [GRID], SCALE is at least 1".into(),
+            );
         }
-        // println!("{}-----------", vis_cnt);
+        let input = args.get(4).map_or("src/puzzle.txt", |s| s.as_str());
+        let grid = parse_grid(&fs::read_to_string(input)?)?;
+
+        fs::create_dir_all(outdir)?;
+        for (name, img) in [
+            ("heights", render_heights(&grid, scale)),
+            ("visible", render_visibility(&grid, scale)),
+            ("scenic", render_scenic(&grid, scale)),
+        ] {
+            let path = outdir.join(format!("{}.{}", name, ext));
+            img.save(&path)?;
+            eprintln!("wrote {} ({}x{})", path.display(), img.width, img.height);
+        }
+        return Ok(());
+    }
+
+    let (rules, rest) = parse_rules(&args)?;
+
+    if rest.first().map(|a| a.as_str()) == Some("top") {
+        let (query, rest) = parse_query(&rest[1..])?;
+        let input = rest.first().map_or("src/puzzle.txt", |s| s.as_str());
+        let grid = parse_grid(&fs::read_to_string(input)?)?;
+
+        for (rank, p) in best_placements(&grid, &rules, &query).iter().enumerate() {
+            println!(
+                "{}\tscore={}\tx={}\ty={}\theight={}\tviews={:?}",
+                rank + 1,
+                p.score,
+                p.x,
+                p.y,
+                p.height,
+                p.views
+            );
+        }
+        return Ok(());
     }
 
+    let input = rest.first().map_or("src/puzzle.txt", |s| s.as_str());
+    let contents = fs::read_to_string(input)?;
+    let grid = parse_grid(contents.as_str())?;
+
+    if rules != SightRules::default() {
+        let survey = survey(&grid, &rules);
+        println!("{:?}", rules);
+        println!(
+            "total_cnt: {}",
+            survey.visible.iter().flatten().filter(|v| **v).count()
+        );
+        let query = PlacementQuery {
+            top: 1,
+            ..PlacementQuery::default()
+        };
+        let best = &best_placements(&grid, &rules, &query)[0];
+        println!("highest_scenic: {}", best.score);
+        println!("at x={} y={} views: {:?}", best.x, best.y, best.views);
+        return Ok(());
+    }
+
+    let rows = grid.len();
+    let cols = grid[0].len();
+    let edge_cnt = edge_count(rows, cols);
+
+    // Part 1
+    let total_cnt = visibility(&grid).iter().flatten().filter(|v| **v).count();
+    let vis_cnt = total_cnt - edge_cnt;
+
+    // Part 2, edge trees always score 0 so the whole grid can be included
+    let scenic = scenic_scores(&grid);
+
     println!("vis_cnt: {}", vis_cnt);
-    println!("total_cnt: {}", edge_cnt + vis_cnt as usize);
-    println!("highest_scenic: {:?}", scenic.iter().max().unwrap());
+    println!("total_cnt: {}", total_cnt);
+    println!(
+        "highest_scenic: {:?}",
+        scenic.iter().flatten().max().unwrap()
+    );
 
     Ok(())
 }
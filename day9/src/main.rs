@@ -1,6 +1,29 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+const TESTMOVES: &str = "R 4
+U 4
+L 3
+D 1
+R 4
+D 1
+L 5
+R 2";
+
+#[cfg(test)]
+const TESTMOVES2: &str = "R 5
+U 8
+L 8
+D 3
+R 17
+D 10
+L 25
+U 20";
 
 #[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
 enum Dir {
@@ -8,118 +31,417 @@ enum Dir {
     D,
     L,
     R,
+    UL,
+    UR,
+    DL,
+    DR,
+}
+
+impl Dir {
+    fn parse(s: &str) -> Option<Dir> {
+        match s {
+            "U" => Some(Dir::U),
+            "D" => Some(Dir::D),
+            "L" => Some(Dir::L),
+            "R" => Some(Dir::R),
+            "UL" => Some(Dir::UL),
+            "UR" => Some(Dir::UR),
+            "DL" => Some(Dir::DL),
+            "DR" => Some(Dir::DR),
+            _ => None,
+        }
+    }
+
+    // One step in this direction as (dx, dy), up is +y
+    fn delta(&self) -> (i32, i32) {
+        match self {
+            Dir::U => (0, 1),
+            Dir::D => (0, -1),
+            Dir::L => (-1, 0),
+            Dir::R => (1, 0),
+            Dir::UL => (-1, 1),
+            Dir::UR => (1, 1),
+            Dir::DL => (-1, -1),
+            Dir::DR => (1, -1),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
-struct Point {
+struct Pos {
     x: i32,
     y: i32,
-    dir: Dir, // Only for moves, combining data structures for puzzle convenience
 }
 
-impl Point {
-    fn new(x: i32, y: i32, dir: Dir) -> Self {
-        Point { x, y, dir }
+impl Pos {
+    fn new(x: i32, y: i32) -> Self {
+        Pos { x, y }
     }
 
-    fn check_and_mv(&mut self, head: &Point) -> bool {
-        let delta = Point::new(head.x - self.x, head.y - self.y, head.dir);
+    // Take one step towards `head` if it is more than `slack` away
+    fn check_and_mv(&mut self, head: &Pos, slack: i32, physics: &Physics) -> bool {
+        let delta = Pos::new(head.x - self.x, head.y - self.y);
+
+        if physics.metric.dist(delta.x, delta.y) <= slack {
+            return false;
+        }
 
-        if delta.x.abs() > 1 || delta.y.abs() > 1 {
-            if delta.x.abs().is_positive() {
+        match physics.follow {
+            Follow::Diagonal => {
                 self.x += delta.x.signum();
-            }
-            if delta.y.abs().is_positive() {
                 self.y += delta.y.signum();
             }
-            return true;
+            // Close the bigger gap first, x on a tie
+            Follow::Orthogonal if delta.x.abs() >= delta.y.abs() => self.x += delta.x.signum(),
+            Follow::Orthogonal => self.y += delta.y.signum(),
         }
 
-        return false;
+        true
     }
 
-    // Step by one
-    fn step(&mut self, dir: Dir) {
-        match dir {
-            Dir::R => {
-                self.x += 1;
-            }
-            Dir::L => {
-                self.x -= 1;
-            }
-            Dir::U => {
-                self.y += 1;
-            }
-            Dir::D => {
-                self.y -= 1;
-            }
+    // Step by one, `sign` of -1 steps the opposite way
+    fn step(&mut self, dir: Dir, sign: i32) {
+        let (dx, dy) = dir.delta();
+        self.x += dx * sign;
+        self.y += dy * sign;
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Metric {
+    // max(|dx|, |dy|), diagonal neighbours are touching
+    Chebyshev,
+    // |dx| + |dy|, only edge neighbours are touching
+    Manhattan,
+}
+
+impl Metric {
+    fn dist(&self, dx: i32, dy: i32) -> i32 {
+        match self {
+            Metric::Chebyshev => dx.abs().max(dy.abs()),
+            Metric::Manhattan => dx.abs() + dy.abs(),
         }
     }
 }
 
-// U 10, R 3 to direction and coordinates
-fn dir_to_point(dir: &str) -> Point {
-    let tmp: Vec<&str> = dir.split(" ").collect::<Vec<&str>>();
+// How a knot catches up once it is too far behind
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Follow {
+    // Straight at the knot ahead, diagonally if needed
+    Diagonal,
+    // Only up, down, left or right. A knot still moves one step per tick, so after
+    // diagonal moves ahead of it the rope can stay stretched past its slack for a while.
+    Orthogonal,
+}
 
-    match tmp[0] {
-        "U" => Point::new(0, tmp[1].parse::<i32>().unwrap(), Dir::U),
-        "D" => Point::new(0, -tmp[1].parse::<i32>().unwrap(), Dir::D),
-        "L" => Point::new(-tmp[1].parse::<i32>().unwrap(), 0, Dir::L),
-        "R" => Point::new(tmp[1].parse::<i32>().unwrap(), 0, Dir::R),
-        _ => {
-            panic!("!!! dir_to_point");
+// Rope model for perform_moves_n. The default is the puzzle's rope.
+#[derive(Debug, Clone, PartialEq)]
+struct Physics {
+    // How far each knot may trail the one ahead before it moves, first entry is the
+    // knot right behind the head. Knots past the end of the list use the last entry.
+    slack: Vec<i32>,
+    metric: Metric,
+    follow: Follow,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Physics {
+            slack: vec![1],
+            metric: Metric::Chebyshev,
+            follow: Follow::Diagonal,
         }
     }
 }
 
-// nknots counts the head as one of the knots, so nknots=10 would have 9 tail knots
-// Return set of all places tail has seen, head point, tail points
-fn perform_moves_n(moves: Vec<Point>, nknots: usize) -> (HashSet<Point>, Point, Vec<Point>) {
-    let mut head = Point::new(0, 0, Dir::L);
-    let mut seen: HashSet<Point> = HashSet::new();
-    seen.insert(Point::new(0, 0, Dir::L));
+impl Physics {
+    // `behind` is 0 for the knot following the head
+    fn slack(&self, behind: usize) -> i32 {
+        self.slack
+            .get(behind)
+            .or(self.slack.last())
+            .copied()
+            .unwrap_or(1)
+    }
+}
 
-    let mut knots: Vec<Point> = Vec::new();
-    for _ in 0..nknots {
-        knots.push(Point::new(0, 0, Dir::L));
+// One line of input: a direction and how many steps to take, negative counts go the
+// opposite way
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Move {
+    dir: Dir,
+    steps: i32,
+}
+
+impl Move {
+    // U 10, R 3, DL 2, L -4
+    fn parse(line: &str) -> Result<Move, String> {
+        match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            // i32::MIN has no positive counterpart to walk the other way
+            [dir, steps] => Ok(Move {
+                dir: Dir::parse(dir).ok_or(format!("unknown direction {:?}", dir))?,
+                steps: steps
+                    .parse()
+                    .ok()
+                    .filter(|&n| n != i32::MIN)
+                    .ok_or(format!("bad step count {:?}", steps))?,
+            }),
+            _ => Err(format!("expected \"DIR STEPS\", got {:?}", line)),
+        }
     }
+}
 
-    for mv in moves {
-        for _ in 0..(mv.x.abs() + mv.y.abs()) {
-            head.step(mv.dir);
+// Run the moves, calling `on_step` with the head and the knots (tail first) at the start
+// and after every head step
+fn simulate<F: FnMut(&Pos, &[Pos])>(
+    moves: &[Move],
+    nknots: usize,
+    physics: &Physics,
+    mut on_step: F,
+) {
+    let mut head = Pos::new(0, 0);
+    let mut knots: Vec<Pos> = vec![Pos::new(0, 0); nknots];
+    on_step(&head, &knots);
 
-            #[allow(unused)]
-            let mut moved = false;
+    for mv in moves {
+        for _ in 0..mv.steps.unsigned_abs() {
+            head.step(mv.dir, mv.steps.signum());
 
             for k in (0..knots.len()).rev() {
+                let slack = physics.slack(knots.len() - 1 - k);
                 if k < knots.len() - 1 {
-                    let np: Point = knots[k + 1].clone();
-                    moved = knots[k].check_and_mv(&np);
+                    let np: Pos = knots[k + 1];
+                    knots[k].check_and_mv(&np, slack, physics);
                 } else {
-                    moved = knots[k].check_and_mv(&head);
-                }
-                if moved && k == 0 {
-                    seen.insert(Point::new(knots[0].x, knots[0].y, Dir::L));
+                    knots[k].check_and_mv(&head, slack, physics);
                 }
             }
+
+            on_step(&head, &knots);
         }
     }
+}
+
+// nknots is the number of knots behind the head, so nknots=9 is a rope of ten
+// Return set of all places tail has seen, head point, tail points
+fn perform_moves_n(
+    moves: &[Move],
+    nknots: usize,
+    physics: &Physics,
+) -> (HashSet<Pos>, Pos, Vec<Pos>) {
+    let mut seen: HashSet<Pos> = HashSet::new();
+    seen.insert(Pos::new(0, 0));
+    let mut last = (Pos::new(0, 0), Vec::new());
 
-    (seen, head, knots)
+    simulate(moves, nknots, physics, |head, knots| {
+        if let Some(tail) = knots.first() {
+            seen.insert(*tail);
+        }
+        last = (*head, knots.to_vec());
+    });
+
+    (seen, last.0, last.1)
 }
 
-fn moves_from_vec(lines: Vec<&str>) -> Vec<Point> {
+// The whole rope after each step, head first and tail last
+fn record_frames(moves: &[Move], nknots: usize, physics: &Physics) -> Vec<Vec<Pos>> {
+    let mut frames = Vec::new();
+    simulate(moves, nknots, physics, |head, knots| {
+        let mut rope = vec![*head];
+        rope.extend(knots.iter().rev());
+        frames.push(rope);
+    });
+    frames
+}
+
+// Where one knot has been
+#[derive(Debug, Clone, Default, PartialEq)]
+struct KnotStats {
+    // How many times the knot arrived at each cell, the start counts as one arrival
+    visits: HashMap<Pos, u32>,
+    // Step the knot first moved on, counting head steps from 1
+    first_move: Option<usize>,
+}
+
+// Everything one simulation says about each knot. A knot only follows the ones ahead of
+// it, so a run with n knots also answers every shorter rope.
+#[derive(Debug, Clone, PartialEq)]
+struct RopeStats {
+    // Head first
+    knots: Vec<KnotStats>,
+    // Box around the whole rope at the start and after each step
+    extent: Vec<Viewport>,
+}
+
+fn rope_stats(moves: &[Move], nknots: usize, physics: &Physics) -> RopeStats {
+    let mut knots = vec![KnotStats::default(); nknots + 1];
+    let mut extent = Vec::new();
+    let mut prev: Vec<Pos> = Vec::new();
+
+    simulate(moves, nknots, physics, |head, tail| {
+        let step = extent.len();
+        let rope: Vec<Pos> = std::iter::once(*head)
+            .chain(tail.iter().rev().copied())
+            .collect();
+        for (i, p) in rope.iter().enumerate() {
+            let moved = prev.get(i).is_some_and(|q| q != p);
+            if prev.is_empty() || moved {
+                *knots[i].visits.entry(*p).or_insert(0) += 1;
+            }
+            if moved && knots[i].first_move.is_none() {
+                knots[i].first_move = Some(step);
+            }
+        }
+        extent.push(Viewport::fit(&rope));
+        prev = rope;
+    });
+
+    RopeStats { knots, extent }
+}
+
+// Visit counts laid out on a grid, rows top down
+#[derive(Debug, Clone, PartialEq)]
+struct Heatmap {
+    view: Viewport,
+    counts: Vec<Vec<u32>>,
+}
+
+impl RopeStats {
+    // Box around everywhere the rope has been
+    fn bounds(&self) -> Viewport {
+        let corners: Vec<Pos> = self
+            .extent
+            .iter()
+            .flat_map(|v| [Pos::new(v.minx, v.miny), Pos::new(v.maxx, v.maxy)])
+            .collect();
+        Viewport::fit(&corners)
+    }
+
+    // Visits of one knot, or of all knots added up
+    fn heatmap(&self, knot: Option<usize>) -> Result<Heatmap, String> {
+        let view = self.bounds();
+        let mut counts = vec![vec![0; view.width() as usize]; view.height() as usize];
+        let knots = match knot {
+            Some(k) => self.knots.get(k..=k).ok_or(format!(
+                "no knot {}, the rope has knots 0 to {}",
+                k,
+                self.knots.len() - 1
+            ))?,
+            None => &self.knots[..],
+        };
+
+        for (p, n) in knots.iter().flat_map(|k| k.visits.iter()) {
+            counts[(view.maxy - p.y) as usize][(p.x - view.minx) as usize] += n;
+        }
+
+        Ok(Heatmap { view, counts })
+    }
+}
+
+impl Heatmap {
+    // Shades scaled to the busiest cell, which gets the darkest, blank for never visited
+    fn render(&self) -> String {
+        const SHADES: &[u8] = b" .:-=+*#%@";
+        let max = self
+            .counts
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let mut s = String::new();
+        for row in &self.counts {
+            for &n in row {
+                let shade = match (n, max) {
+                    (0, _) => 0,
+                    (_, 1) => SHADES.len() - 1,
+                    _ => 1 + (n - 1) as usize * (SHADES.len() - 2) / (max - 1) as usize,
+                };
+                s.push(SHADES[shade] as char);
+            }
+            s.push('\n');
+        }
+        s
+    }
+}
+
+// Blank lines are skipped, anything else that doesn't parse is an error with its line
+fn moves_from_vec(lines: Vec<&str>) -> Result<Vec<Move>, String> {
     lines
         .iter()
-        .map(|l| dir_to_point(&l))
-        .collect::<Vec<Point>>()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| Move::parse(l).map_err(|e| format!("line {}: {}", n + 1, e)))
+        .collect()
+}
+
+// Inclusive range of grid cells to draw
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Viewport {
+    minx: i32,
+    maxx: i32,
+    miny: i32,
+    maxy: i32,
 }
 
-fn render_seen(seen: &HashSet<Point>, dim: (i32, i32, i32, i32)) -> String {
+impl Viewport {
+    // Smallest viewport holding all of `points`, just the origin if there are none
+    fn fit<'a, I: IntoIterator<Item = &'a Pos>>(points: I) -> Viewport {
+        let mut view: Option<Viewport> = None;
+        for p in points {
+            view = Some(match view {
+                None => Viewport {
+                    minx: p.x,
+                    maxx: p.x,
+                    miny: p.y,
+                    maxy: p.y,
+                },
+                Some(v) => Viewport {
+                    minx: v.minx.min(p.x),
+                    maxx: v.maxx.max(p.x),
+                    miny: v.miny.min(p.y),
+                    maxy: v.maxy.max(p.y),
+                },
+            });
+        }
+        view.unwrap_or(Viewport {
+            minx: 0,
+            maxx: 0,
+            miny: 0,
+            maxy: 0,
+        })
+    }
+
+    fn width(&self) -> i32 {
+        self.maxx - self.minx + 1
+    }
+
+    fn height(&self) -> i32 {
+        self.maxy - self.miny + 1
+    }
+
+    // A window of at most w x h inside this viewport, centered on `at` as far as the
+    // edges allow
+    fn window(&self, at: &Pos, w: i32, h: i32) -> Viewport {
+        let w = w.clamp(1, self.width());
+        let h = h.clamp(1, self.height());
+        let minx = (at.x - w / 2).clamp(self.minx, self.maxx - w + 1);
+        let miny = (at.y - h / 2).clamp(self.miny, self.maxy - h + 1);
+        Viewport {
+            minx,
+            maxx: minx + w - 1,
+            miny,
+            maxy: miny + h - 1,
+        }
+    }
+}
+
+fn render_seen(seen: &HashSet<Pos>, view: &Viewport) -> String {
     let mut s: String = String::new();
-    for y in (dim.2..(dim.3 + 1)).rev() {
-        for x in dim.0..(dim.1 + 1) {
-            if seen.get(&Point::new(x, y, Dir::L)).is_some() {
+    for y in (view.miny..(view.maxy + 1)).rev() {
+        for x in view.minx..(view.maxx + 1) {
+            if seen.contains(&Pos::new(x, y)) {
                 s.push('#');
             } else {
                 s.push('.')
@@ -130,22 +452,474 @@ fn render_seen(seen: &HashSet<Point>, dim: (i32, i32, i32, i32)) -> String {
     s
 }
 
+// H for the head, then 1-9 and a-z down the rope
+fn knot_label(i: usize) -> char {
+    match i {
+        0 => 'H',
+        _ => char::from_digit(i as u32, 36).unwrap_or('*'),
+    }
+}
+
+// One frame: the tail's trail as #, knots drawn over it with the ones nearer the head on top
+fn render_frame(rope: &[Pos], trail: &HashSet<Pos>, view: &Viewport) -> String {
+    let mut grid = vec![vec!['.'; view.width() as usize]; view.height() as usize];
+    let mut put = |p: &Pos, c: char| {
+        if (view.minx..=view.maxx).contains(&p.x) && (view.miny..=view.maxy).contains(&p.y) {
+            grid[(view.maxy - p.y) as usize][(p.x - view.minx) as usize] = c;
+        }
+    };
+
+    for p in trail {
+        put(p, '#');
+    }
+    for (i, p) in rope.iter().enumerate().rev() {
+        put(p, knot_label(i));
+    }
+
+    let mut s = String::new();
+    for row in grid {
+        s.extend(row);
+        s.push('\n');
+    }
+    s
+}
+
+// Play the frames on a terminal, the window follows the head once the rope's range
+// doesn't fit in w x h
+fn animate<W: Write>(
+    out: &mut W,
+    frames: &[Vec<Pos>],
+    w: i32,
+    h: i32,
+    delay: Duration,
+) -> io::Result<()> {
+    let all = Viewport::fit(frames.iter().flatten());
+    let mut trail = HashSet::new();
+
+    for (n, rope) in frames.iter().enumerate() {
+        trail.extend(rope.last());
+        let view = all.window(&rope[0], w, h);
+        writeln!(
+            out,
+            "\x1b[H\x1b[2J{}step {}/{}",
+            render_frame(rope, &trail, &view),
+            n,
+            frames.len() - 1
+        )?;
+        out.flush()?;
+        thread::sleep(delay);
+    }
+
+    Ok(())
+}
+
+// Animated SVG of the frames, played once at `fps` frames per second. Knots are
+// squares jumping between cells, trail cells appear when the tail first gets there.
+fn to_svg(frames: &[Vec<Pos>], cell: u32, fps: u32) -> String {
+    let view = Viewport::fit(frames.iter().flatten());
+    let cell = cell as i32;
+    let px = |p: &Pos| ((p.x - view.minx) * cell, (view.maxy - p.y) * cell);
+    let dur = frames.len() as f64 / fps as f64;
+
+    let mut s = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"#111\"/>\n",
+        view.width() * cell,
+        view.height() * cell
+    );
+
+    let mut trail = HashSet::new();
+    for (n, rope) in frames.iter().enumerate() {
+        let Some(tail) = rope.last() else { continue };
+        if trail.insert(*tail) {
+            let (x, y) = px(tail);
+            s += &format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#555\" \
+                 visibility=\"hidden\"><set attributeName=\"visibility\" to=\"visible\" \
+                 begin=\"{:.3}s\" fill=\"freeze\"/></rect>\n",
+                x,
+                y,
+                cell,
+                cell,
+                n as f64 / fps as f64
+            );
+        }
+    }
+
+    // Tail first so the head is drawn on top
+    let nknots = frames.first().map_or(0, |r| r.len());
+    for i in (0..nknots).rev() {
+        let (xs, ys): (Vec<String>, Vec<String>) = frames
+            .iter()
+            .map(|rope| {
+                let (x, y) = px(&rope[i]);
+                (x.to_string(), y.to_string())
+            })
+            .unzip();
+        let (x, y) = px(&frames[0][i]);
+        let fill = if i == 0 { "#e33" } else { "#3ae" };
+        s += &format!(
+            "<g><rect x=\"{x}\" y=\"{y}\" width=\"{cell}\" height=\"{cell}\" fill=\"{fill}\">\
+             <title>{}</title>\
+             <animate attributeName=\"x\" values=\"{}\" dur=\"{dur:.3}s\" calcMode=\"discrete\" fill=\"freeze\"/>\
+             <animate attributeName=\"y\" values=\"{}\" dur=\"{dur:.3}s\" calcMode=\"discrete\" fill=\"freeze\"/>\
+             </rect></g>\n",
+            knot_label(i),
+            xs.join(";"),
+            ys.join(";"),
+        );
+    }
+
+    s += "</svg>\n";
+    s
+}
+
+#[test]
+fn test_sample_moves() {
+    let moves = moves_from_vec(TESTMOVES.split('\n').collect()).unwrap();
+    assert_eq!(perform_moves_n(&moves, 1, &Physics::default()).0.len(), 13);
+    assert_eq!(perform_moves_n(&moves, 9, &Physics::default()).0.len(), 1);
+
+    let moves = moves_from_vec(TESTMOVES2.split('\n').collect()).unwrap();
+    assert_eq!(perform_moves_n(&moves, 9, &Physics::default()).0.len(), 36);
+}
+
+#[test]
+fn test_parse_moves() {
+    let moves = moves_from_vec(vec!["UL 12", "DR -3", "", "U 0"]).unwrap();
+    assert_eq!(
+        moves,
+        vec![
+            Move {
+                dir: Dir::UL,
+                steps: 12
+            },
+            Move {
+                dir: Dir::DR,
+                steps: -3
+            },
+            Move {
+                dir: Dir::U,
+                steps: 0
+            },
+        ]
+    );
+
+    // DR -3 is UL 3, so the head ends up 15 up and left
+    let (_, head, _) = perform_moves_n(&moves, 1, &Physics::default());
+    assert_eq!(head, Pos::new(-15, 15));
+
+    assert_eq!(
+        moves_from_vec(vec!["U 1", "X 2"]).unwrap_err(),
+        "line 2: unknown direction \"X\""
+    );
+    assert_eq!(
+        moves_from_vec(vec!["U two"]).unwrap_err(),
+        "line 1: bad step count \"two\""
+    );
+    assert_eq!(
+        moves_from_vec(vec!["U -2147483648"]).unwrap_err(),
+        "line 1: bad step count \"-2147483648\""
+    );
+    assert_eq!(
+        moves_from_vec(vec!["U"]).unwrap_err(),
+        "line 1: expected \"DIR STEPS\", got \"U\""
+    );
+}
+
+// Rope physics from --slack, --metric and --no-diagonal. They are removed from
+// `args` the same way take_flag removes the other flags.
+fn parse_physics(args: &mut Vec<String>) -> Result<Physics, Box<dyn Error>> {
+    let mut physics = Physics::default();
+
+    if let Some(slack) = take_flag(args, "--slack")? {
+        physics.slack = slack
+            .split(',')
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()?;
+    }
+    if let Some(metric) = take_flag(args, "--metric")? {
+        physics.metric = match metric.as_str() {
+            "chebyshev" => Metric::Chebyshev,
+            "manhattan" => Metric::Manhattan,
+            m => return Err(format!("unknown metric {}", m).into()),
+        };
+    }
+
+    let before = args.len();
+    args.retain(|a| a != "--no-diagonal");
+    if args.len() < before {
+        physics.follow = Follow::Orthogonal;
+    }
+
+    Ok(physics)
+}
+
+#[test]
+fn test_physics() {
+    let right = moves_from_vec(vec!["R 5"]).unwrap();
+
+    // Slack 2 keeps the tail two behind
+    let slack2 = Physics {
+        slack: vec![2],
+        ..Physics::default()
+    };
+    let (seen, _, knots) = perform_moves_n(&right, 1, &slack2);
+    assert_eq!(knots, vec![Pos::new(3, 0)]);
+    assert_eq!(seen.len(), 4);
+
+    // Per segment slack: the knot behind the head keeps 1, the one after that 3
+    let mixed = Physics {
+        slack: vec![1, 3],
+        ..Physics::default()
+    };
+    let (_, _, knots) = perform_moves_n(&right, 2, &mixed);
+    assert_eq!(knots, vec![Pos::new(1, 0), Pos::new(4, 0)]);
+
+    // Diagonal neighbours are too far apart under Manhattan distance
+    let corner = moves_from_vec(vec!["R 1", "U 1"]).unwrap();
+    let manhattan = Physics {
+        metric: Metric::Manhattan,
+        ..Physics::default()
+    };
+    assert_eq!(
+        perform_moves_n(&corner, 1, &manhattan).2,
+        vec![Pos::new(1, 1)]
+    );
+    assert_eq!(
+        perform_moves_n(&corner, 1, &Physics::default()).2,
+        vec![Pos::new(0, 0)]
+    );
+
+    let orthogonal = Physics {
+        metric: Metric::Manhattan,
+        follow: Follow::Orthogonal,
+        ..Physics::default()
+    };
+    assert_eq!(
+        perform_moves_n(&corner, 1, &orthogonal).2,
+        vec![Pos::new(1, 0)]
+    );
+
+    // Without diagonal steps a knot closes the bigger gap first
+    let no_diag = Physics {
+        follow: Follow::Orthogonal,
+        ..Physics::default()
+    };
+    let mut knot = Pos::new(0, 0);
+    assert!(knot.check_and_mv(&Pos::new(1, 2), 1, &no_diag));
+    assert_eq!(knot, Pos::new(0, 1));
+    assert!(!knot.check_and_mv(&Pos::new(1, 2), 1, &no_diag));
+}
+
+#[test]
+fn test_render_frames() {
+    let moves = moves_from_vec(TESTMOVES.split('\n').collect()).unwrap();
+    let frames = record_frames(&moves, 9, &Physics::default());
+    assert_eq!(frames.len(), 25);
+    assert!(frames[0].iter().all(|p| *p == Pos::new(0, 0)));
+
+    // After R 4, as drawn in the puzzle text
+    let view = Viewport {
+        minx: 0,
+        maxx: 5,
+        miny: 0,
+        maxy: 4,
+    };
+    let trail = HashSet::from([Pos::new(0, 0)]);
+    assert_eq!(
+        render_frame(&frames[4], &trail, &view),
+        "......\n......\n......\n......\n4321H.\n"
+    );
+
+    let all = Viewport::fit(frames.iter().flatten());
+    assert_eq!((all.width(), all.height()), (6, 5));
+    assert_eq!(
+        all.window(&Pos::new(4, 4), 3, 2),
+        Viewport {
+            minx: 3,
+            maxx: 5,
+            miny: 3,
+            maxy: 4
+        }
+    );
+
+    let svg = to_svg(&frames, 10, 5);
+    assert_eq!(svg.matches("<animate ").count(), 20);
+    assert_eq!(svg.matches("<set ").count(), 1);
+    assert!(svg.contains("width=\"60\" height=\"50\""));
+}
+
+#[test]
+fn test_rope_stats() {
+    let moves = moves_from_vec(TESTMOVES2.split('\n').collect()).unwrap();
+    let stats = rope_stats(&moves, 9, &Physics::default());
+
+    // One run gives the tail counts of every rope length
+    for n in 1..=9 {
+        let (seen, _, _) = perform_moves_n(&moves, n, &Physics::default());
+        assert_eq!(stats.knots[n].visits.len(), seen.len());
+    }
+    assert_eq!(stats.knots[9].visits.len(), 36);
+
+    // Along R 5 each knot starts moving one step after the one ahead of it
+    let first: Vec<Option<usize>> = stats.knots.iter().map(|k| k.first_move).collect();
+    assert_eq!(&first[..4], &[Some(1), Some(2), Some(3), Some(4)]);
+    assert_eq!(stats.knots[9].first_move, Some(17));
+
+    // The head arrives somewhere on every step
+    let steps: u32 = stats.knots[0].visits.values().sum();
+    assert_eq!(steps as usize, stats.extent.len());
+    assert_eq!(stats.extent.len(), 5 + 8 + 8 + 3 + 17 + 10 + 25 + 20 + 1);
+    assert_eq!(stats.extent[0].width(), 1);
+    assert_eq!(stats.extent[5].width(), 6);
+
+    let heat = stats.heatmap(Some(9)).unwrap();
+    assert_eq!(heat.view, stats.bounds());
+    assert_eq!(heat.counts.iter().flatten().filter(|&&n| n > 0).count(), 36);
+    let all = stats.heatmap(None).unwrap();
+    assert_eq!(all.render().lines().count(), all.view.height() as usize);
+    assert_eq!(
+        stats.heatmap(Some(10)).unwrap_err(),
+        "no knot 10, the rope has knots 0 to 9"
+    );
+
+    // The busiest cell gets the darkest shade, the quietest the lightest
+    let heat = Heatmap {
+        view: Viewport::fit(&[Pos::new(0, 0), Pos::new(3, 0)]),
+        counts: vec![vec![0, 1, 5, 9]],
+    };
+    assert_eq!(heat.render(), " .+@\n");
+}
+
+// Remove `flag` and the value after it from `args`, so what's left are the
+// positional arguments. The last one wins if the flag is given more than once.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let mut value = None;
+    while let Some(i) = args.iter().position(|a| a == flag) {
+        if i + 1 >= args.len() {
+            return Err(format!("{} needs a value", flag));
+        }
+        value = Some(args.remove(i + 1));
+        args.remove(i);
+    }
+    Ok(value)
+}
+
+#[test]
+fn test_take_flag() {
+    let mut args: Vec<String> = [
+        "--input", "in.txt", "--knots", "2", "svg", "out.svg", "--fps", "5",
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .collect();
+    assert_eq!(
+        take_flag(&mut args, "--input"),
+        Ok(Some("in.txt".to_string()))
+    );
+    assert_eq!(take_flag(&mut args, "--knots"), Ok(Some("2".to_string())));
+    assert_eq!(take_flag(&mut args, "--delay"), Ok(None));
+    assert_eq!(take_flag(&mut args, "--fps"), Ok(Some("5".to_string())));
+    assert_eq!(args, vec!["svg", "out.svg"]);
+
+    let mut args = vec!["trail".to_string(), "--knots".to_string()];
+    assert_eq!(
+        take_flag(&mut args, "--knots"),
+        Err("--knots needs a value".to_string())
+    );
+
+    let mut args: Vec<String> = ["trail", "--no-diagonal", "--slack", "2,1", "--input", "x"]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+    let physics = parse_physics(&mut args).unwrap();
+    assert_eq!(physics.slack, vec![2, 1]);
+    assert_eq!(physics.follow, Follow::Orthogonal);
+    assert_eq!(args, vec!["trail", "--input", "x"]);
+}
+
+// day9 [--slack N[,N...]] [--metric chebyshev|manhattan] [--no-diagonal] [--input FILE]
+//      [trail [--knots N] | animate [--knots N] [--delay MS] [--size WxH]
+//             | svg OUT [--knots N] [--fps F] [--cell PX]
+//             | stats [--knots N] [--heat K|all]]
 fn main() -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string("src/puzzle.txt")?;
-    let lines: Vec<&str> = contents.split("\n").collect::<Vec<&str>>();
+    let mut rest: Vec<String> = std::env::args().skip(1).collect();
+    let physics = parse_physics(&mut rest)?;
+
+    // Flags first, whatever is left are the command and its arguments
+    let path = take_flag(&mut rest, "--input")?.unwrap_or("src/puzzle.txt".to_string());
+    let knots = take_flag(&mut rest, "--knots")?;
+    let nknots: usize = knots.as_deref().map_or(Ok(9), |n| n.parse())?;
+    let delay: u64 = take_flag(&mut rest, "--delay")?.map_or(Ok(50), |d| d.parse())?;
+    let size = take_flag(&mut rest, "--size")?.unwrap_or("80x23".to_string());
+    let fps: u32 = take_flag(&mut rest, "--fps")?.map_or(Ok(30), |f| f.parse())?;
+    let cell: u32 = take_flag(&mut rest, "--cell")?.map_or(Ok(4), |c| c.parse())?;
+    let heat = take_flag(&mut rest, "--heat")?;
+    if let Some(flag) = rest.iter().find(|a| a.starts_with("--")) {
+        return Err(format!("unknown flag {}", flag).into());
+    }
 
-    let moves = moves_from_vec(lines);
-    let (seen, _, _) = perform_moves_n(moves.clone(), 1);
+    let contents = fs::read_to_string(&path)?;
+    let lines: Vec<&str> = contents.split('\n').collect::<Vec<&str>>();
+    let moves = moves_from_vec(lines)?;
+
+    match rest.first().map(|a| a.as_str()) {
+        Some("animate") => {
+            let (w, h) = size.split_once('x').ok_or("--size wants WxH")?;
+            let frames = record_frames(&moves, nknots, &physics);
+            animate(
+                &mut io::stdout().lock(),
+                &frames,
+                w.parse()?,
+                h.parse()?,
+                Duration::from_millis(delay),
+            )?;
+            return Ok(());
+        }
+        Some("svg") => {
+            let out = rest.get(1).ok_or("svg needs an output file")?;
+            let frames = record_frames(&moves, nknots, &physics);
+            fs::write(out, to_svg(&frames, cell, fps.max(1)))?;
+            eprintln!("wrote {} frames to {}", frames.len(), out);
+            return Ok(());
+        }
+        Some("stats") => {
+            let stats = rope_stats(&moves, nknots, &physics);
+            for (i, k) in stats.knots.iter().enumerate() {
+                println!(
+                    "knot {} visited {} cells, first moved on step {}",
+                    knot_label(i),
+                    k.visits.len(),
+                    k.first_move.map_or("-".to_string(), |s| s.to_string())
+                );
+            }
+            let b = stats.bounds();
+            println!("bounds x {}..{} y {}..{}", b.minx, b.maxx, b.miny, b.maxy);
+            match heat.as_deref() {
+                Some("all") => print!("{}", stats.heatmap(None)?.render()),
+                Some(k) => print!("{}", stats.heatmap(Some(k.parse()?))?.render()),
+                None => {}
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let trail = rest.first().map(|a| a.as_str()) == Some("trail");
+    if knots.is_some() && !trail {
+        return Err("--knots only works with trail, animate, svg and stats".into());
+    }
+
+    let (seen, _, _) = perform_moves_n(&moves, 1, &physics);
     eprintln!("seen = {:?}", seen.len());
-    let (seen, _, _) = perform_moves_n(moves, 9);
+    let (seen, _, _) = perform_moves_n(&moves, 9, &physics);
     eprintln!("seen = {:?}", seen.len());
 
-    let minx = seen.iter().map(|p| p.x).min().unwrap_or(0);
-    let maxx = seen.iter().map(|p| p.x).max().unwrap_or(0);
-    let miny = seen.iter().map(|p| p.y).min().unwrap_or(0);
-    let maxy = seen.iter().map(|p| p.y).max().unwrap_or(0);
-
-    // print!("{}", render_seen(&seen, (minx, maxx, miny, maxy)));
+    if trail {
+        let (seen, _, _) = perform_moves_n(&moves, nknots, &physics);
+        print!("{}", render_seen(&seen, &Viewport::fit(&seen)));
+    }
 
     Ok(())
 }